@@ -0,0 +1,12 @@
+//! Wall-clock time as the Unix seconds stored in Redis entries and webhook
+//! signatures.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the Unix epoch, or 0 if the clock is set before it.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
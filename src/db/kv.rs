@@ -0,0 +1,294 @@
+//! Short-lived keyed data: sessions, OAuth state, rate limit counters and
+//! caches. It lives in Redis, except on a SQLite deployment without
+//! `REDIS_URL`, where it is kept in this process instead. That copy is lost on
+//! restart and not shared between instances, so it only suits a single one.
+
+use redis::aio::MultiplexedConnection;
+use redis::{AsyncCommands, RedisResult};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+#[derive(Clone)]
+pub enum Kv {
+    Redis(MultiplexedConnection),
+    Memory(Arc<Memory>),
+}
+
+impl Kv {
+    pub fn memory() -> Self {
+        Kv::Memory(Arc::default())
+    }
+
+    pub async fn get(&self, key: &str) -> RedisResult<Option<String>> {
+        match self {
+            Kv::Redis(redis) => redis.clone().get(key).await,
+            Kv::Memory(memory) => Ok(memory.entries().text(key, Instant::now())),
+        }
+    }
+
+    pub async fn set_ex(&self, key: &str, value: &str, ttl: u64) -> RedisResult<()> {
+        match self {
+            Kv::Redis(redis) => redis.clone().set_ex(key, value, ttl).await,
+            Kv::Memory(memory) => {
+                let now = Instant::now();
+                memory
+                    .entries()
+                    .insert(key, Value::Text(value.into()), now + secs(ttl), now);
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads and removes an entry in one step, so only one caller gets it.
+    pub async fn get_del(&self, key: &str) -> RedisResult<Option<String>> {
+        match self {
+            Kv::Redis(redis) => redis.clone().get_del(key).await,
+            Kv::Memory(memory) => {
+                let mut entries = memory.entries();
+                let value = entries.text(key, Instant::now());
+                entries.map.remove(key);
+                Ok(value)
+            }
+        }
+    }
+
+    pub async fn del(&self, keys: &[String]) -> RedisResult<()> {
+        match self {
+            Kv::Redis(redis) => redis.clone().del(keys).await,
+            Kv::Memory(memory) => {
+                let mut entries = memory.entries();
+                for key in keys {
+                    entries.map.remove(key);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Seconds until `key` expires, or a negative number when it doesn't
+    /// exist, as Redis `TTL` reports it.
+    pub async fn ttl(&self, key: &str) -> RedisResult<i64> {
+        match self {
+            Kv::Redis(redis) => redis.clone().ttl(key).await,
+            Kv::Memory(memory) => {
+                let now = Instant::now();
+                Ok(memory
+                    .entries()
+                    .live(key, now)
+                    .map_or(-2, |entry| remaining(entry, now)))
+            }
+        }
+    }
+
+    /// Counts a hit in the fixed window at `key`, starting the window on the
+    /// first one. Returns the hits so far and the seconds left in the window.
+    pub async fn hit(&self, key: &str, window: u64) -> RedisResult<(u64, i64)> {
+        match self {
+            Kv::Redis(redis) => {
+                redis::pipe()
+                    .atomic()
+                    .cmd("SET")
+                    .arg(key)
+                    .arg(0)
+                    .arg("EX")
+                    .arg(window)
+                    .arg("NX")
+                    .ignore()
+                    .incr(key, 1)
+                    .ttl(key)
+                    .query_async(&mut redis.clone())
+                    .await
+            }
+            Kv::Memory(memory) => {
+                let now = Instant::now();
+                let mut entries = memory.entries();
+                if let Some(entry) = entries.live(key, now) {
+                    if let Value::Text(count) = &mut entry.value {
+                        let next = count.parse::<u64>().unwrap_or_default() + 1;
+                        *count = next.to_string();
+                        return Ok((next, remaining(entry, now)));
+                    }
+                }
+                entries.insert(key, Value::Text("1".into()), now + secs(window), now);
+                Ok((1, window as i64))
+            }
+        }
+    }
+
+    /// Adds `member` to the set at `key` with `score`, drops members scored at
+    /// or below `now`, and keeps the set for `ttl` seconds. Returns every
+    /// member left, lowest score first.
+    pub async fn track(
+        &self,
+        key: &str,
+        member: &str,
+        score: u64,
+        now: u64,
+        ttl: u64,
+    ) -> RedisResult<Vec<String>> {
+        match self {
+            Kv::Redis(redis) => {
+                let (members,): (Vec<String>,) = redis::pipe()
+                    .atomic()
+                    .zrembyscore(key, "-inf", now)
+                    .ignore()
+                    .zadd(key, member, score)
+                    .ignore()
+                    .expire(key, ttl as i64)
+                    .ignore()
+                    .zrange(key, 0, -1)
+                    .query_async(&mut redis.clone())
+                    .await?;
+                Ok(members)
+            }
+            Kv::Memory(memory) => {
+                let instant = Instant::now();
+                let mut entries = memory.entries();
+                let mut scores = match entries.map.remove(key) {
+                    Some(Entry {
+                        value: Value::Sorted(scores),
+                        expires_at,
+                    }) if expires_at > instant => scores,
+                    _ => HashMap::new(),
+                };
+                scores.retain(|_, score| *score > now);
+                scores.insert(member.into(), score);
+
+                let mut members: Vec<(&String, &u64)> = scores.iter().collect();
+                members.sort_by(|a, b| a.1.cmp(b.1).then_with(|| a.0.cmp(b.0)));
+                let members = members.into_iter().map(|(id, _)| id.clone()).collect();
+                entries.insert(key, Value::Sorted(scores), instant + secs(ttl), instant);
+                Ok(members)
+            }
+        }
+    }
+
+    /// Every member of the set at `key`, lowest score first.
+    pub async fn members(&self, key: &str) -> RedisResult<Vec<String>> {
+        match self {
+            Kv::Redis(redis) => redis.clone().zrange(key, 0, -1).await,
+            Kv::Memory(memory) => {
+                let mut entries = memory.entries();
+                let Some(Entry {
+                    value: Value::Sorted(scores),
+                    ..
+                }) = entries.live(key, Instant::now())
+                else {
+                    return Ok(Vec::new());
+                };
+                let mut members: Vec<(&String, &u64)> = scores.iter().collect();
+                members.sort_by(|a, b| a.1.cmp(b.1).then_with(|| a.0.cmp(b.0)));
+                Ok(members.into_iter().map(|(id, _)| id.clone()).collect())
+            }
+        }
+    }
+
+    /// Removes `members` from the set at `key`.
+    pub async fn untrack(&self, key: &str, members: &[String]) -> RedisResult<()> {
+        match self {
+            Kv::Redis(redis) => redis.clone().zrem(key, members).await,
+            Kv::Memory(memory) => {
+                let mut entries = memory.entries();
+                if let Some(Entry {
+                    value: Value::Sorted(scores),
+                    ..
+                }) = entries.live(key, Instant::now())
+                {
+                    for member in members {
+                        scores.remove(member);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn ping(&self) -> RedisResult<()> {
+        match self {
+            Kv::Redis(redis) => redis::cmd("PING")
+                .query_async::<String>(&mut redis.clone())
+                .await
+                .map(drop),
+            Kv::Memory(_) => Ok(()),
+        }
+    }
+}
+
+/// How often expired entries are swept out of [`Memory`]. Reads skip them
+/// either way; this only bounds how long they take up space.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The in-process stand-in for Redis. Every entry expires, like everything
+/// this service puts in Redis.
+#[derive(Default)]
+pub struct Memory {
+    entries: Mutex<Entries>,
+}
+
+impl Memory {
+    fn entries(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    swept_at: Option<Instant>,
+}
+
+struct Entry {
+    value: Value,
+    expires_at: Instant,
+}
+
+enum Value {
+    Text(String),
+    /// Members and their scores, like a Redis sorted set.
+    Sorted(HashMap<String, u64>),
+}
+
+impl Entries {
+    /// The entry at `key` unless it has expired, in which case it is dropped.
+    fn live(&mut self, key: &str, now: Instant) -> Option<&mut Entry> {
+        if self
+            .map
+            .get(key)
+            .is_some_and(|entry| entry.expires_at <= now)
+        {
+            self.map.remove(key);
+        }
+        self.map.get_mut(key)
+    }
+
+    fn text(&mut self, key: &str, now: Instant) -> Option<String> {
+        match self.live(key, now) {
+            Some(Entry {
+                value: Value::Text(text),
+                ..
+            }) => Some(text.clone()),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, key: &str, value: Value, expires_at: Instant, now: Instant) {
+        if self
+            .swept_at
+            .is_none_or(|at| now.duration_since(at) >= SWEEP_INTERVAL)
+        {
+            self.map.retain(|_, entry| entry.expires_at > now);
+            self.swept_at = Some(now);
+        }
+        self.map.insert(key.into(), Entry { value, expires_at });
+    }
+}
+
+fn secs(seconds: u64) -> Duration {
+    Duration::from_secs(seconds)
+}
+
+/// Whole seconds left on `entry`, rounded up so a live entry never reports 0.
+fn remaining(entry: &Entry, now: Instant) -> i64 {
+    entry.expires_at.duration_since(now).as_secs_f64().ceil() as i64
+}
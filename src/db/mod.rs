@@ -1,11 +1,27 @@
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::middleware::cors::CorsOrigins;
+use std::sync::Arc;
+
+pub mod kv;
 pub mod postgres;
 pub mod redis_helper;
-
-pub type Db = sqlx::PgPool;
-pub type Redis = redis::aio::MultiplexedConnection;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod store;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub db: Db,
-    pub redis: Redis,
+    /// Timezone records; prefer this over querying `timezones` directly. Bulk
+    /// reads go to the replica when one is configured.
+    pub store: Arc<dyn store::TimezoneStore>,
+    /// API keys, in the same database as the timezone records.
+    pub keys: Arc<dyn store::KeyStore>,
+    /// Redis, or its in-process stand-in.
+    pub kv: kv::Kv,
+    pub http: reqwest::Client,
+    pub config: Arc<Config>,
+    pub cors_origins: CorsOrigins,
+    /// Timezone changes for `/events` subscribers.
+    pub events: EventBus,
 }
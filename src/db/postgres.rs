@@ -1,12 +1,25 @@
-use sqlx::{postgres::PgPoolOptions, PgPool};
-use std::env;
+use crate::config::Config;
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    PgPool,
+};
+use std::str::FromStr;
 
-pub async fn connect() -> PgPool {
-    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is required");
+/// Connection options for `url`, with `DB_STATEMENT_TIMEOUT` applied so
+/// Postgres cancels runaway queries instead of letting them hold a pooled
+/// connection.
+fn connect_options(config: &Config, url: &str) -> PgConnectOptions {
+    let options = PgConnectOptions::from_str(url).expect("Invalid Postgres URL");
+    match config.db_statement_timeout {
+        Some(timeout) => options.options([("statement_timeout", timeout.as_millis().to_string())]),
+        None => options,
+    }
+}
 
+pub async fn connect(config: &Config) -> PgPool {
     let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&db_url)
+        .max_connections(config.db_max_connections)
+        .connect_with(connect_options(config, &config.database_url))
         .await
         .expect("Failed to connect to Postgres");
 
@@ -23,5 +36,207 @@ pub async fn connect() -> PgPool {
     .await
     .expect("Failed to create timezones table");
 
+    sqlx::query("ALTER TABLE timezones ADD COLUMN IF NOT EXISTS avatar TEXT;")
+        .execute(&pool)
+        .await
+        .expect("Failed to add avatar column");
+
+    sqlx::query("ALTER TABLE timezones ADD COLUMN IF NOT EXISTS time_format TEXT;")
+        .execute(&pool)
+        .await
+        .expect("Failed to add time_format column");
+
+    sqlx::query(
+        r#"
+        ALTER TABLE timezones
+            ADD COLUMN IF NOT EXISTS latitude DOUBLE PRECISION,
+            ADD COLUMN IF NOT EXISTS longitude DOUBLE PRECISION;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to add coordinate columns");
+
+    sqlx::query(
+        "ALTER TABLE timezones ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT now();",
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to add updated_at column");
+
+    // Deleted rows leave nothing behind to take MAX(updated_at) of, so the
+    // time of the latest deletion is kept separately in a single row.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS timezones_deleted (
+            singleton BOOLEAN PRIMARY KEY DEFAULT true CHECK (singleton),
+            at TIMESTAMPTZ NOT NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create timezones_deleted table");
+
+    // Backstop for writes that skip the handlers' validation. NOT VALID keeps
+    // startup working if old rows predate the rule; new writes are checked.
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (
+                SELECT 1 FROM pg_constraint WHERE conname = 'timezones_timezone_length'
+            ) THEN
+                ALTER TABLE timezones ADD CONSTRAINT timezones_timezone_length
+                    CHECK (length(timezone) > 0 AND length(timezone) <= 64) NOT VALID;
+            END IF;
+        END
+        $$;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to add timezone length constraint");
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            key_hash TEXT NOT NULL UNIQUE,
+            scope TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            revoked_at TIMESTAMPTZ
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create api_keys table");
+
+    // Data migrations run once; each records its version here.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version TEXT PRIMARY KEY,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create schema_migrations table");
+
+    verify_schema(&pool).await;
     pool
 }
+
+/// Every column the queries rely on, with its `information_schema` type.
+/// Features that add a column add it here too.
+const EXPECTED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("timezones", "user_id", "text"),
+    ("timezones", "username", "text"),
+    ("timezones", "timezone", "text"),
+    ("timezones", "avatar", "text"),
+    ("timezones", "time_format", "text"),
+    ("timezones", "latitude", "double precision"),
+    ("timezones", "longitude", "double precision"),
+    ("timezones", "updated_at", "timestamp with time zone"),
+    ("timezones_deleted", "singleton", "boolean"),
+    ("timezones_deleted", "at", "timestamp with time zone"),
+    ("api_keys", "id", "text"),
+    ("api_keys", "name", "text"),
+    ("api_keys", "key_hash", "text"),
+    ("api_keys", "scope", "text"),
+    ("api_keys", "created_at", "timestamp with time zone"),
+    ("api_keys", "revoked_at", "timestamp with time zone"),
+    ("schema_migrations", "version", "text"),
+    (
+        "schema_migrations",
+        "applied_at",
+        "timestamp with time zone",
+    ),
+];
+
+/// Fails startup, listing every problem at once, when a table edited by hand
+/// or left behind by an old deployment lacks a column or has one of the wrong
+/// type. Otherwise that would only surface as a query error in some handler.
+async fn verify_schema(pool: &PgPool) {
+    let tables: Vec<&str> = EXPECTED_COLUMNS
+        .iter()
+        .map(|(table, _, _)| *table)
+        .collect();
+    let found: Vec<(String, String, String)> = sqlx::query_as(
+        r#"
+        SELECT table_name::TEXT, column_name::TEXT, data_type::TEXT
+        FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = ANY($1)
+        "#,
+    )
+    .bind(&tables)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to read the database schema");
+
+    let problems: Vec<String> = EXPECTED_COLUMNS
+        .iter()
+        .filter_map(|(table, column, kind)| {
+            match found.iter().find(|(t, c, _)| t == table && c == column) {
+                None => Some(format!("{}.{} is missing", table, column)),
+                Some((_, _, actual)) if actual != kind => Some(format!(
+                    "{}.{} is {}, expected {}",
+                    table, column, actual, kind
+                )),
+                Some(_) => None,
+            }
+        })
+        .collect();
+    if !problems.is_empty() {
+        panic!("Database schema does not match: {}", problems.join("; "));
+    }
+}
+
+/// Connects to `DATABASE_READ_URL`, falling back to the primary pool when no
+/// replica is configured. The schema is owned by the primary, so nothing is
+/// created here.
+pub async fn connect_read(config: &Config, primary: &PgPool) -> PgPool {
+    let Some(url) = &config.database_read_url else {
+        return primary.clone();
+    };
+    PgPoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .connect_with(connect_options(config, url))
+        .await
+        .expect("Failed to connect to Postgres read replica")
+}
+
+/// Notes that rows were just deleted from `timezones`, for `last_modified`.
+pub async fn record_deletion<'e, E>(executor: E) -> Result<(), sqlx::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    sqlx::query(
+        r#"
+        INSERT INTO timezones_deleted (at) VALUES (now())
+        ON CONFLICT (singleton) DO UPDATE SET at = EXCLUDED.at
+        "#,
+    )
+    .execute(executor)
+    .await
+    .map(drop)
+}
+
+/// Unix time of the latest change to `timezones`, whether a write or a
+/// deletion, or `None` if nothing has been stored yet.
+pub async fn last_modified(pool: &PgPool) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT FLOOR(EXTRACT(EPOCH FROM GREATEST(
+            (SELECT MAX(updated_at) FROM timezones),
+            (SELECT at FROM timezones_deleted)
+        )))::BIGINT
+        "#,
+    )
+    .fetch_one(pool)
+    .await
+}
@@ -1,12 +1,152 @@
+//! Redis holds two kinds of data. Sessions are required: without them nobody
+//! can log in, so their call sites surface Redis failures as 503s. Everything
+//! else (rate limit counters, caches) is optional and must log and carry on
+//! when Redis is unavailable.
+
+use crate::config::Config;
+use crate::db::kv::Kv;
+use rand::Rng;
 use redis::aio::MultiplexedConnection;
-use redis::Client;
-use std::env;
-
-pub async fn connect() -> MultiplexedConnection {
-    let url = env::var("REDIS_URL").expect("REDIS_URL is required");
-    let client = Client::open(url).expect("Failed to create Redis client");
-    client
-        .get_multiplexed_tokio_connection()
+use redis::{AsyncConnectionConfig, Client, RedisResult};
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::Duration;
+use tracing::warn;
+
+/// Every command on the returned connection fails with a timeout error after
+/// `REDIS_COMMAND_TIMEOUT_MS`, so a hung Redis degrades requests (503 for
+/// sessions, a skipped cache otherwise) instead of stalling them. Connecting
+/// is bounded the same way, so a hung Redis fails startup rather than
+/// stalling it. Without
+/// `REDIS_URL` everything is kept in process instead.
+pub async fn connect(config: &Config) -> Kv {
+    let Some(url) = &config.redis_url else {
+        return Kv::memory();
+    };
+    let redis = open(url, config.redis_command_timeout)
+        .await
+        .expect("Failed to connect to Redis");
+    Kv::Redis(redis)
+}
+
+async fn open(url: &str, timeout: Duration) -> RedisResult<MultiplexedConnection> {
+    let options = AsyncConnectionConfig::new()
+        .set_connection_timeout(timeout)
+        .set_response_timeout(timeout);
+    Client::open(url)?
+        .get_multiplexed_async_connection_with_config(&options)
         .await
-        .expect("Failed to connect to Redis")
+}
+
+/// Spreads `base` by up to ±`pct` percent so keys written in a burst (e.g.
+/// logins right after a deploy) don't all expire in the same second.
+pub fn ttl_with_jitter(base: u64, pct: u8) -> u64 {
+    let spread = base * u64::from(pct.min(100)) / 100;
+    if spread == 0 {
+        return base;
+    }
+    let low = base - spread;
+    rand::thread_rng().gen_range(low..=base + spread).max(1)
+}
+
+pub struct RateLimitStatus {
+    /// Hits in the current window, including this one.
+    pub count: u64,
+    /// Seconds until the window resets.
+    pub reset_in: u64,
+}
+
+/// Counts a hit against a fixed-window limiter. The window starts with the
+/// first hit.
+pub async fn hit_rate_limit(kv: &Kv, key: &str, window: u64) -> RedisResult<RateLimitStatus> {
+    let (count, ttl) = kv.hit(key, window).await?;
+    Ok(RateLimitStatus {
+        count,
+        reset_in: u64::try_from(ttl).unwrap_or(window),
+    })
+}
+
+/// Reads a JSON cache entry. Misses, Redis errors and undecodable entries all
+/// come back as `None`.
+pub async fn cache_get<T: DeserializeOwned>(kv: &Kv, key: &str) -> Option<T> {
+    match kv.get(key).await {
+        Ok(value) => value.and_then(|raw| serde_json::from_str(&raw).ok()),
+        Err(err) => {
+            warn!("Cache read for {} failed: {}", key, err);
+            None
+        }
+    }
+}
+
+pub async fn cache_set<T: Serialize>(kv: &Kv, key: &str, value: &T, ttl: u64) {
+    let Ok(raw) = serde_json::to_string(value) else {
+        return;
+    };
+    if let Err(err) = kv.set_ex(key, &raw, ttl).await {
+        warn!("Cache write for {} failed: {}", key, err);
+    }
+}
+
+pub async fn cache_del(kv: &Kv, key: &str) {
+    if let Err(err) = kv.del(&[key.to_string()]).await {
+        warn!("Cache invalidation for {} failed: {}", key, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::AsyncCommands;
+    use std::time::Instant;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+    const SLACK: Duration = Duration::from_millis(500);
+
+    /// A server that accepts connections and never replies, like a Redis
+    /// stuck mid-failover. With `handshake` it first acknowledges the
+    /// `CLIENT SETINFO` commands a new connection sends, so connecting works.
+    async fn silent_server(handshake: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    while let Ok(read) = socket.read(&mut buf).await {
+                        if read == 0 {
+                            break;
+                        }
+                        let setinfo = buf[..read]
+                            .windows(b"SETINFO".len())
+                            .filter(|window| *window == b"SETINFO")
+                            .count();
+                        if handshake {
+                            for _ in 0..setinfo {
+                                socket.write_all(b"+OK\r\n").await.ok();
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        format!("redis://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn commands_time_out_when_redis_stops_replying() {
+        let mut redis = open(&silent_server(true).await, TIMEOUT).await.unwrap();
+
+        let started = Instant::now();
+        let result = redis.get::<_, Option<String>>("session:test").await;
+        assert!(result.is_err_and(|err| err.is_timeout()));
+        assert!(started.elapsed() < TIMEOUT + SLACK);
+    }
+
+    #[tokio::test]
+    async fn connecting_times_out_when_redis_never_replies() {
+        let started = Instant::now();
+        assert!(open(&silent_server(false).await, TIMEOUT).await.is_err());
+        assert!(started.elapsed() < TIMEOUT + SLACK);
+    }
 }
@@ -0,0 +1,656 @@
+//! Timezone records and API keys in a single SQLite file, for small
+//! deployments that would rather not run Postgres. Built with
+//! `--features sqlite` and selected with a `sqlite://` `DATABASE_URL`.
+
+use crate::db::store::{
+    EntryFix, ExportRecord, KeyRecord, KeyStore, MergeConflict, MergeOutcome, StoreFuture,
+    TimezoneRecord, TimezoneStore, TimezoneWrite, MIGRATION_BATCH,
+};
+use futures_util::stream::BoxStream;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+    Sqlite, SqlitePool, Transaction,
+};
+use std::str::FromStr;
+use tracing::warn;
+
+/// Write transactions take the lock up front; a deferred one that reads first
+/// fails instead of waiting when another writer commits in between.
+const BEGIN_WRITE: &str = "BEGIN IMMEDIATE";
+
+const COLUMNS: &str = "user_id, username, timezone, avatar, time_format, latitude, longitude";
+
+pub struct SqliteTimezoneStore {
+    pool: SqlitePool,
+}
+
+/// Opens (creating if needed) the database at `url` and brings its schema up
+/// to date.
+pub async fn connect(url: &str, max_connections: u32) -> SqliteTimezoneStore {
+    let options = SqliteConnectOptions::from_str(url)
+        .expect("Invalid DATABASE_URL")
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(max_connections)
+        .connect_with(options)
+        .await
+        .expect("Failed to open SQLite database");
+
+    // Times are Unix seconds; SQLite has no timestamp type to keep them in.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS timezones (
+            user_id TEXT PRIMARY KEY,
+            username TEXT NOT NULL,
+            timezone TEXT NOT NULL
+                CHECK (length(timezone) > 0 AND length(timezone) <= 64),
+            avatar TEXT,
+            time_format TEXT,
+            updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create timezones table");
+
+    // SQLite has no ADD COLUMN IF NOT EXISTS.
+    for (column, kind) in [("latitude", "REAL"), ("longitude", "REAL")] {
+        let exists = sqlx::query("SELECT 1 FROM pragma_table_info('timezones') WHERE name = $1")
+            .bind(column)
+            .fetch_optional(&pool)
+            .await
+            .expect("Failed to read timezones columns")
+            .is_some();
+        if !exists {
+            sqlx::query(&format!(
+                "ALTER TABLE timezones ADD COLUMN {} {}",
+                column, kind
+            ))
+            .execute(&pool)
+            .await
+            .expect("Failed to add coordinate columns");
+        }
+    }
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS timezones_deleted (
+            singleton INTEGER PRIMARY KEY DEFAULT 1 CHECK (singleton = 1),
+            at INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create timezones_deleted table");
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            key_hash TEXT NOT NULL UNIQUE,
+            scope TEXT NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+            revoked_at INTEGER
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create api_keys table");
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version TEXT PRIMARY KEY,
+            applied_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create schema_migrations table");
+
+    SqliteTimezoneStore { pool }
+}
+
+async fn record_deletion(tx: &mut Transaction<'_, Sqlite>) -> Result<(), sqlx::Error> {
+    // `singleton` is the rowid, which ignores its DEFAULT: left out, the
+    // second deletion would get row 2 and fail the CHECK.
+    sqlx::query(
+        r#"
+        INSERT INTO timezones_deleted (singleton, at) VALUES (1, unixepoch())
+        ON CONFLICT (singleton) DO UPDATE SET at = EXCLUDED.at
+        "#,
+    )
+    .execute(&mut **tx)
+    .await
+    .map(drop)
+}
+
+async fn rename(tx: &mut Transaction<'_, Sqlite>, from: &str, to: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE timezones SET user_id = $2, updated_at = unixepoch() WHERE user_id = $1")
+        .bind(from)
+        .bind(to)
+        .execute(&mut **tx)
+        .await
+        .map(drop)
+}
+
+async fn exists(tx: &mut Transaction<'_, Sqlite>, user_id: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query("SELECT 1 FROM timezones WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(&mut **tx)
+        .await
+        .map(|row| row.is_some())
+}
+
+impl TimezoneStore for SqliteTimezoneStore {
+    fn get<'a>(&'a self, user_id: &'a str) -> StoreFuture<'a, Option<TimezoneRecord>> {
+        Box::pin(async move {
+            sqlx::query_as(&format!(
+                "SELECT {} FROM timezones WHERE user_id = $1",
+                COLUMNS
+            ))
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+        })
+    }
+
+    fn batch<'a>(&'a self, user_ids: &'a [String]) -> StoreFuture<'a, Vec<TimezoneRecord>> {
+        Box::pin(async move {
+            // No array binds in SQLite, so the IDs travel as one JSON array.
+            let ids = serde_json::to_string(user_ids).unwrap_or_default();
+            sqlx::query_as(&format!(
+                "SELECT {} FROM timezones WHERE user_id IN (SELECT value FROM json_each($1))",
+                COLUMNS
+            ))
+            .bind(ids)
+            .fetch_all(&self.pool)
+            .await
+        })
+    }
+
+    fn list(&self) -> StoreFuture<'_, Vec<TimezoneRecord>> {
+        Box::pin(async move {
+            sqlx::query_as(&format!(
+                "SELECT {} FROM timezones ORDER BY user_id",
+                COLUMNS
+            ))
+            .fetch_all(&self.pool)
+            .await
+        })
+    }
+
+    fn count(&self) -> StoreFuture<'_, i64> {
+        Box::pin(async move {
+            sqlx::query_scalar("SELECT COUNT(*) FROM timezones")
+                .fetch_one(&self.pool)
+                .await
+        })
+    }
+
+    fn same_zone<'a>(
+        &'a self,
+        timezone: &'a str,
+        except: &'a str,
+        after: &'a str,
+        limit: i64,
+    ) -> StoreFuture<'a, (Vec<TimezoneRecord>, i64)> {
+        Box::pin(async move {
+            let records = sqlx::query_as(&format!(
+                r#"
+                SELECT {} FROM timezones
+                WHERE timezone = $1 AND user_id <> $2 AND user_id > $3
+                ORDER BY user_id
+                LIMIT $4
+                "#,
+                COLUMNS
+            ))
+            .bind(timezone)
+            .bind(except)
+            .bind(after)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+            let count = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM timezones WHERE timezone = $1 AND user_id <> $2",
+            )
+            .bind(timezone)
+            .bind(except)
+            .fetch_one(&self.pool)
+            .await?;
+            Ok((records, count))
+        })
+    }
+
+    fn export(&self) -> BoxStream<'_, Result<ExportRecord, sqlx::Error>> {
+        sqlx::query_as(
+            r#"
+            SELECT user_id, username, timezone, avatar, time_format, latitude, longitude,
+                strftime('%Y-%m-%dT%H:%M:%SZ', updated_at, 'unixepoch') AS updated_at
+            FROM timezones ORDER BY user_id
+            "#,
+        )
+        .fetch(&self.pool)
+    }
+
+    fn set<'a>(&'a self, write: TimezoneWrite<'a>) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin_with(BEGIN_WRITE).await?;
+            let previous: Option<String> =
+                sqlx::query_scalar("SELECT timezone FROM timezones WHERE user_id = $1")
+                    .bind(write.user_id)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            sqlx::query(
+                r#"
+                INSERT INTO timezones
+                    (user_id, username, timezone, avatar, time_format, latitude, longitude)
+                VALUES ($1, $2, $3, $4, $6, $7, $8)
+                ON CONFLICT (user_id) DO UPDATE
+                SET username = EXCLUDED.username, timezone = EXCLUDED.timezone,
+                    avatar = CASE WHEN $5 THEN EXCLUDED.avatar ELSE timezones.avatar END,
+                    time_format = CASE WHEN $9 THEN $6 ELSE timezones.time_format END,
+                    latitude = CASE WHEN $10 THEN $7 ELSE timezones.latitude END,
+                    longitude = CASE WHEN $10 THEN $8 ELSE timezones.longitude END,
+                    updated_at = unixepoch()
+                "#,
+            )
+            .bind(write.user_id)
+            .bind(write.username)
+            .bind(write.timezone)
+            .bind(write.avatar)
+            .bind(write.replace_avatar)
+            .bind(write.time_format.flatten())
+            .bind(write.coordinates.flatten().map(|c| c.lat))
+            .bind(write.coordinates.flatten().map(|c| c.lon))
+            .bind(write.time_format.is_some())
+            .bind(write.coordinates.is_some())
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            Ok(previous)
+        })
+    }
+
+    fn delete<'a>(&'a self, user_id: &'a str) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin_with(BEGIN_WRITE).await?;
+            let deleted: Option<String> =
+                sqlx::query_scalar("DELETE FROM timezones WHERE user_id = $1 RETURNING timezone")
+                    .bind(user_id)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            if deleted.is_some() {
+                if let Err(err) = record_deletion(&mut tx).await {
+                    warn!("Failed to record deletion: {}", err);
+                }
+            }
+            tx.commit().await?;
+            Ok(deleted)
+        })
+    }
+
+    fn update_profile<'a>(
+        &'a self,
+        user_id: &'a str,
+        username: &'a str,
+        avatar: Option<&'a str>,
+    ) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let result = sqlx::query(
+                r#"
+                UPDATE timezones SET username = $2, avatar = $3, updated_at = unixepoch()
+                WHERE user_id = $1 AND (username IS NOT $2 OR avatar IS NOT $3)
+                "#,
+            )
+            .bind(user_id)
+            .bind(username)
+            .bind(avatar)
+            .execute(&self.pool)
+            .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    fn migrate<'a>(&'a self, version: &'a str, fix: &'a EntryFix) -> StoreFuture<'a, Option<u64>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin_with(BEGIN_WRITE).await?;
+            let claimed = sqlx::query(
+                "INSERT INTO schema_migrations (version) VALUES ($1) ON CONFLICT DO NOTHING",
+            )
+            .bind(version)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+                > 0;
+            if !claimed {
+                return Ok(None);
+            }
+
+            let mut changed = 0;
+            let mut after = String::new();
+            loop {
+                let batch: Vec<(String, String, String)> = sqlx::query_as(
+                    r#"
+                    SELECT user_id, username, timezone FROM timezones
+                    WHERE user_id > $1 ORDER BY user_id LIMIT $2
+                    "#,
+                )
+                .bind(&after)
+                .bind(MIGRATION_BATCH)
+                .fetch_all(&mut *tx)
+                .await?;
+                let Some((last, _, _)) = batch.last() else {
+                    break;
+                };
+                after = last.clone();
+
+                for (user_id, username, timezone) in &batch {
+                    if let Some((username, timezone)) = fix(user_id, username, timezone) {
+                        sqlx::query(
+                            "UPDATE timezones SET username = $2, timezone = $3 WHERE user_id = $1",
+                        )
+                        .bind(user_id)
+                        .bind(username)
+                        .bind(timezone)
+                        .execute(&mut *tx)
+                        .await?;
+                        changed += 1;
+                    }
+                }
+            }
+            tx.commit().await?;
+            Ok(Some(changed))
+        })
+    }
+
+    fn merge<'a>(
+        &'a self,
+        from: &'a str,
+        to: &'a str,
+        on_conflict: MergeConflict,
+    ) -> StoreFuture<'a, MergeOutcome> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin_with(BEGIN_WRITE).await?;
+            if !exists(&mut tx, from).await? {
+                return Ok(MergeOutcome::SourceMissing);
+            }
+
+            let outcome = match (exists(&mut tx, to).await?, on_conflict) {
+                (true, MergeConflict::Error) => return Ok(MergeOutcome::TargetExists),
+                (true, MergeConflict::KeepTarget) => {
+                    sqlx::query("DELETE FROM timezones WHERE user_id = $1")
+                        .bind(from)
+                        .execute(&mut *tx)
+                        .await?;
+                    record_deletion(&mut tx).await?;
+                    MergeOutcome::KeptTarget
+                }
+                (true, MergeConflict::KeepSource) => {
+                    sqlx::query("DELETE FROM timezones WHERE user_id = $1")
+                        .bind(to)
+                        .execute(&mut *tx)
+                        .await?;
+                    rename(&mut tx, from, to).await?;
+                    MergeOutcome::ReplacedTarget
+                }
+                (false, _) => {
+                    rename(&mut tx, from, to).await?;
+                    MergeOutcome::Merged
+                }
+            };
+
+            tx.commit().await?;
+            Ok(outcome)
+        })
+    }
+
+    fn remap<'a>(&'a self, from: &'a str, to: &'a str) -> StoreFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            sqlx::query_scalar(
+                "UPDATE timezones SET timezone = $2, updated_at = unixepoch() WHERE timezone = $1 RETURNING user_id",
+            )
+            .bind(from)
+            .bind(to)
+            .fetch_all(&self.pool)
+            .await
+        })
+    }
+
+    fn last_modified(&self) -> StoreFuture<'_, Option<i64>> {
+        Box::pin(async move {
+            sqlx::query_scalar(
+                r#"
+                SELECT NULLIF(MAX(
+                    COALESCE((SELECT MAX(updated_at) FROM timezones), 0),
+                    COALESCE((SELECT at FROM timezones_deleted), 0)
+                ), 0)
+                "#,
+            )
+            .fetch_one(&self.pool)
+            .await
+        })
+    }
+
+    fn ping(&self) -> StoreFuture<'_, ()> {
+        Box::pin(async move { sqlx::query("SELECT 1").execute(&self.pool).await.map(drop) })
+    }
+
+    fn pool_size(&self) -> u32 {
+        self.pool.size()
+    }
+}
+
+impl KeyStore for SqliteTimezoneStore {
+    fn find_key<'a>(
+        &'a self,
+        key_hash: &'a str,
+    ) -> StoreFuture<'a, Option<(String, String, String)>> {
+        Box::pin(async move {
+            sqlx::query_as(
+                "SELECT id, name, scope FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+            )
+            .bind(key_hash)
+            .fetch_optional(&self.pool)
+            .await
+        })
+    }
+
+    fn list_keys(&self) -> StoreFuture<'_, Vec<KeyRecord>> {
+        Box::pin(async move {
+            let rows: Vec<(String, String, String, String, bool)> = sqlx::query_as(
+                r#"
+                SELECT id, name, scope, datetime(created_at, 'unixepoch'), revoked_at IS NOT NULL
+                FROM api_keys
+                ORDER BY created_at, rowid
+                "#,
+            )
+            .fetch_all(&self.pool)
+            .await?;
+            Ok(rows.into_iter().map(KeyRecord::from).collect())
+        })
+    }
+
+    fn insert_key<'a>(
+        &'a self,
+        id: &'a str,
+        name: &'a str,
+        key_hash: &'a str,
+        scope: &'a str,
+    ) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let result = sqlx::query(
+                r#"
+                INSERT INTO api_keys (id, name, key_hash, scope) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (key_hash) DO NOTHING
+                "#,
+            )
+            .bind(id)
+            .bind(name)
+            .bind(key_hash)
+            .bind(scope)
+            .execute(&self.pool)
+            .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    fn revoke_key<'a>(&'a self, id: &'a str) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let result = sqlx::query(
+                "UPDATE api_keys SET revoked_at = unixepoch() WHERE id = $1 AND revoked_at IS NULL",
+            )
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::store::Coordinates;
+
+    /// Every connection to `sqlite::memory:` opens its own empty database,
+    /// so the pool gets just one.
+    async fn store() -> SqliteTimezoneStore {
+        connect("sqlite::memory:", 1).await
+    }
+
+    fn write<'a>(user_id: &'a str, timezone: &'a str) -> TimezoneWrite<'a> {
+        TimezoneWrite {
+            user_id,
+            username: "someone",
+            timezone,
+            avatar: None,
+            replace_avatar: false,
+            time_format: None,
+            coordinates: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn set_get_round_trip() {
+        let store = store().await;
+        assert_eq!(store.set(write("1", "Europe/London")).await.unwrap(), None);
+        let previous = store
+            .set(TimezoneWrite {
+                avatar: Some("abc"),
+                replace_avatar: true,
+                time_format: Some(Some("24h")),
+                coordinates: Some(Some(Coordinates {
+                    lat: 51.5,
+                    lon: -0.1,
+                })),
+                ..write("1", "Europe/Paris")
+            })
+            .await
+            .unwrap();
+        assert_eq!(previous.as_deref(), Some("Europe/London"));
+
+        let record = store.get("1").await.unwrap().unwrap();
+        assert_eq!(record.timezone, "Europe/Paris");
+        assert_eq!(record.avatar.as_deref(), Some("abc"));
+        assert_eq!(record.time_format.as_deref(), Some("24h"));
+        assert_eq!(
+            (record.latitude, record.longitude),
+            (Some(51.5), Some(-0.1))
+        );
+        assert!(store.get("2").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn set_leaves_or_clears_optional_fields() {
+        let store = store().await;
+        store
+            .set(TimezoneWrite {
+                avatar: Some("abc"),
+                replace_avatar: true,
+                time_format: Some(Some("12h")),
+                ..write("1", "Asia/Tokyo")
+            })
+            .await
+            .unwrap();
+
+        store.set(write("1", "Asia/Tokyo")).await.unwrap();
+        let record = store.get("1").await.unwrap().unwrap();
+        assert_eq!(record.avatar.as_deref(), Some("abc"));
+        assert_eq!(record.time_format.as_deref(), Some("12h"));
+
+        store
+            .set(TimezoneWrite {
+                time_format: Some(None),
+                ..write("1", "Asia/Tokyo")
+            })
+            .await
+            .unwrap();
+        assert_eq!(store.get("1").await.unwrap().unwrap().time_format, None);
+    }
+
+    #[tokio::test]
+    async fn batch_returns_stored_ids_only() {
+        let store = store().await;
+        store.set(write("1", "UTC")).await.unwrap();
+        store.set(write("2", "Asia/Tokyo")).await.unwrap();
+
+        let ids = ["1".to_string(), "2".to_string(), "3".to_string()];
+        let mut found: Vec<String> = store
+            .batch(&ids)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|record| record.user_id)
+            .collect();
+        found.sort();
+        assert_eq!(found, ["1", "2"]);
+        assert!(store.batch(&[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_returns_timezone_and_records_every_deletion() {
+        let store = store().await;
+        assert_eq!(store.last_modified().await.unwrap(), None);
+        store.set(write("1", "UTC")).await.unwrap();
+        store.set(write("2", "Asia/Tokyo")).await.unwrap();
+
+        assert_eq!(store.delete("1").await.unwrap().as_deref(), Some("UTC"));
+        assert_eq!(store.delete("1").await.unwrap(), None);
+        assert_eq!(
+            store.delete("2").await.unwrap().as_deref(),
+            Some("Asia/Tokyo")
+        );
+        assert_eq!(store.count().await.unwrap(), 0);
+        assert!(store.last_modified().await.unwrap().is_some());
+
+        let deletions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM timezones_deleted")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap();
+        assert_eq!(deletions, 1);
+    }
+
+    #[tokio::test]
+    async fn migration_runs_once() {
+        let store = store().await;
+        store.set(write("1", "US/Eastern")).await.unwrap();
+        let fix: &EntryFix = &|_: &str, username: &str, timezone: &str| {
+            (timezone == "US/Eastern")
+                .then(|| (username.to_string(), "America/New_York".to_string()))
+        };
+
+        assert_eq!(store.migrate("test", fix).await.unwrap(), Some(1));
+        assert_eq!(store.migrate("test", fix).await.unwrap(), None);
+        assert_eq!(
+            store.get("1").await.unwrap().unwrap().timezone,
+            "America/New_York"
+        );
+    }
+}
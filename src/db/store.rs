@@ -0,0 +1,748 @@
+//! Timezone records behind a trait, so handlers don't depend on the SQL of a
+//! particular backend.
+
+use crate::config::{Config, SeedTimezone};
+use crate::db::postgres;
+use crate::tz;
+use chrono_tz::Tz;
+use futures_util::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use sqlx::{error::ErrorKind, PgPool};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// A stored timezone entry.
+#[derive(Clone, sqlx::FromRow)]
+pub struct TimezoneRecord {
+    pub user_id: String,
+    pub username: String,
+    pub timezone: String,
+    pub avatar: Option<String>,
+    pub time_format: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+impl TimezoneRecord {
+    pub fn coordinates(&self) -> Option<Coordinates> {
+        Some(Coordinates {
+            lat: self.latitude?,
+            lon: self.longitude?,
+        })
+    }
+}
+
+/// A record as exported for operators, with its last change as an RFC 3339
+/// UTC time.
+#[derive(sqlx::FromRow)]
+pub struct ExportRecord {
+    #[sqlx(flatten)]
+    pub record: TimezoneRecord,
+    pub updated_at: String,
+}
+
+/// A location the user opted to share. Only ever shown back to its owner.
+#[derive(Clone, Copy, Serialize)]
+pub struct Coordinates {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// An insert or replace from `/set`.
+pub struct TimezoneWrite<'a> {
+    pub user_id: &'a str,
+    pub username: &'a str,
+    pub timezone: &'a str,
+    pub avatar: Option<&'a str>,
+    /// API key writes don't know the avatar, so only session writes replace
+    /// the stored one.
+    pub replace_avatar: bool,
+    /// Left unchanged when `None`; `Some(None)` clears it.
+    pub time_format: Option<Option<&'a str>>,
+    /// Left unchanged when `None`; `Some(None)` clears it.
+    pub coordinates: Option<Option<Coordinates>>,
+}
+
+/// Corrects one entry during [`TimezoneStore::migrate`].
+pub type EntryFix = dyn Fn(&str, &str, &str) -> Option<(String, String)> + Send + Sync;
+
+/// Entries a migration reads at a time.
+pub const MIGRATION_BATCH: i64 = 500;
+
+/// What a merge does when the target user already has an entry.
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeConflict {
+    KeepTarget,
+    KeepSource,
+    #[default]
+    Error,
+}
+
+pub enum MergeOutcome {
+    SourceMissing,
+    /// Only with `MergeConflict::Error`; nothing was changed.
+    TargetExists,
+    KeptTarget,
+    ReplacedTarget,
+    Merged,
+}
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, sqlx::Error>> + Send + 'a>>;
+
+pub trait TimezoneStore: Send + Sync {
+    /// One record, read so that it reflects the caller's own writes.
+    fn get<'a>(&'a self, user_id: &'a str) -> StoreFuture<'a, Option<TimezoneRecord>>;
+
+    /// The records of any of `user_ids` that have one, in no particular order.
+    fn batch<'a>(&'a self, user_ids: &'a [String]) -> StoreFuture<'a, Vec<TimezoneRecord>>;
+
+    /// Every record, ordered by user ID.
+    fn list(&self) -> StoreFuture<'_, Vec<TimezoneRecord>>;
+
+    fn count(&self) -> StoreFuture<'_, i64>;
+
+    /// Up to `limit` records in `timezone` other than `except`'s, ordered by
+    /// user ID and starting after `after`, plus how many there are in all.
+    fn same_zone<'a>(
+        &'a self,
+        timezone: &'a str,
+        except: &'a str,
+        after: &'a str,
+        limit: i64,
+    ) -> StoreFuture<'a, (Vec<TimezoneRecord>, i64)>;
+
+    /// Every record ordered by user ID, streamed rather than collected.
+    fn export(&self) -> BoxStream<'_, Result<ExportRecord, sqlx::Error>>;
+
+    /// Inserts or replaces a record, returning the timezone it had before.
+    fn set<'a>(&'a self, write: TimezoneWrite<'a>) -> StoreFuture<'a, Option<String>>;
+
+    /// Removes a record, returning its timezone if there was one.
+    fn delete<'a>(&'a self, user_id: &'a str) -> StoreFuture<'a, Option<String>>;
+
+    /// Copies a fresh username and avatar onto an existing record. Returns
+    /// whether anything changed.
+    fn update_profile<'a>(
+        &'a self,
+        user_id: &'a str,
+        username: &'a str,
+        avatar: Option<&'a str>,
+    ) -> StoreFuture<'a, bool>;
+
+    /// Runs the data migration `version` unless `schema_migrations` already
+    /// records it, in one transaction on the primary. `fix` sees each entry's
+    /// user ID, username and timezone and returns the corrected pair when
+    /// they need changing. The rewrite keeps `updated_at`, as a correction
+    /// isn't a change. Returns the number of entries changed, or `None` when
+    /// the migration had already run.
+    fn migrate<'a>(&'a self, version: &'a str, fix: &'a EntryFix) -> StoreFuture<'a, Option<u64>>;
+
+    /// Moves the record of `from` to `to` in one transaction.
+    fn merge<'a>(
+        &'a self,
+        from: &'a str,
+        to: &'a str,
+        on_conflict: MergeConflict,
+    ) -> StoreFuture<'a, MergeOutcome>;
+
+    /// Renames every record stored under timezone `from` to `to`, returning
+    /// the user IDs that changed.
+    fn remap<'a>(&'a self, from: &'a str, to: &'a str) -> StoreFuture<'a, Vec<String>>;
+
+    /// Unix time of the latest write or deletion, if any.
+    fn last_modified(&self) -> StoreFuture<'_, Option<i64>>;
+
+    /// Runs a trivial query, for `/health`.
+    fn ping(&self) -> StoreFuture<'_, ()>;
+
+    /// Connections currently open to the primary.
+    fn pool_size(&self) -> u32;
+}
+
+/// An API key as listed for admins; the key itself is never stored.
+pub struct KeyRecord {
+    pub id: String,
+    pub name: String,
+    pub scope: String,
+    pub created_at: String,
+    pub revoked: bool,
+}
+
+/// API keys, kept in the same database as the timezone records.
+pub trait KeyStore: Send + Sync {
+    /// The unrevoked key with this hash, as `(id, name, scope)`.
+    fn find_key<'a>(
+        &'a self,
+        key_hash: &'a str,
+    ) -> StoreFuture<'a, Option<(String, String, String)>>;
+
+    /// Every key, oldest first.
+    fn list_keys(&self) -> StoreFuture<'_, Vec<KeyRecord>>;
+
+    /// Adds a key unless one with the same hash exists. Returns whether it was
+    /// added.
+    fn insert_key<'a>(
+        &'a self,
+        id: &'a str,
+        name: &'a str,
+        key_hash: &'a str,
+        scope: &'a str,
+    ) -> StoreFuture<'a, bool>;
+
+    /// Revokes a key. Returns false when there is no unrevoked key `id`.
+    fn revoke_key<'a>(&'a self, id: &'a str) -> StoreFuture<'a, bool>;
+}
+
+/// True when a write was rejected by a CHECK constraint, which callers report
+/// as a 400 rather than a 500.
+pub fn is_check_violation(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .is_some_and(|db| db.kind() == ErrorKind::CheckViolation)
+}
+
+/// Pauses before each retry of a read that lost its connection. Short enough
+/// to stay well inside a client's patience, long enough to ride out a pooled
+/// connection being replaced or a replica failing over.
+const READ_RETRY_DELAYS: [Duration; 2] = [Duration::from_millis(50), Duration::from_millis(200)];
+
+/// True for failures of the connection rather than the query: I/O errors and
+/// Postgres connection exceptions (SQLSTATE class 08) or server shutdowns
+/// (57P01 to 57P03). Rerunning the same read may succeed; anything else would
+/// fail the same way again. Pool timeouts are left out on purpose: the pool
+/// is already saturated, and retrying would only queue more work on it.
+fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_) => true,
+        sqlx::Error::Database(db) => db.code().is_some_and(|code| {
+            code.starts_with("08") || matches!(&*code, "57P01" | "57P02" | "57P03")
+        }),
+        _ => false,
+    }
+}
+
+/// Runs a read, trying again after each of `READ_RETRY_DELAYS` while it fails
+/// with a transient error. Only for idempotent reads: a write whose reply was
+/// lost may already have been applied.
+pub async fn retry_read<T, F, Fut>(mut read: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    for delay in READ_RETRY_DELAYS {
+        match read().await {
+            Err(err) if is_transient(&err) => {
+                warn!("Retrying read in {:?} after {}", delay, err);
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+    read().await
+}
+
+/// Connects to `DATABASE_URL`: a SQLite file for `sqlite://` URLs, otherwise
+/// Postgres with its optional replica. Returns the same database twice, once
+/// for timezone records and once for API keys.
+pub async fn connect(config: &Config) -> (Arc<dyn TimezoneStore>, Arc<dyn KeyStore>) {
+    #[cfg(feature = "sqlite")]
+    if config.uses_sqlite() {
+        let store = Arc::new(
+            crate::db::sqlite::connect(&config.database_url, config.db_max_connections).await,
+        );
+        return (store.clone(), store);
+    }
+
+    let primary = postgres::connect(config).await;
+    let replica = postgres::connect_read(config, &primary).await;
+    let store = Arc::new(PgTimezoneStore::new(primary, replica));
+    (store.clone(), store)
+}
+
+/// The version [`normalize`] is recorded under in `schema_migrations`.
+const NORMALIZE_ENTRIES: &str = "0001_normalize_entries";
+
+/// Brings entries written before `/set` trimmed its input in line with what it
+/// stores now: usernames without surrounding whitespace, and zones under their
+/// proper IANA spelling (`america/new_york` becomes `America/New_York`). Links
+/// like `US/Eastern` are left alone, as `/set` keeps them too. Runs once per
+/// database; a failed run isn't recorded, so the next startup tries again.
+pub async fn normalize(store: &dyn TimezoneStore) -> Result<(), sqlx::Error> {
+    if let Some(changed) = store.migrate(NORMALIZE_ENTRIES, &normalized_entry).await? {
+        info!(
+            "Migration {} normalized {} entries",
+            NORMALIZE_ENTRIES, changed
+        );
+    }
+    Ok(())
+}
+
+fn normalized_entry(user_id: &str, username: &str, timezone: &str) -> Option<(String, String)> {
+    let trimmed = username.trim();
+    let new_username = if trimmed.is_empty() {
+        username
+    } else {
+        trimmed
+    };
+
+    let name = timezone.trim();
+    let spelled = name
+        .parse::<Tz>()
+        .ok()
+        .or_else(|| tz::find_case_insensitive(name));
+    let new_timezone = match spelled {
+        Some(tz) => tz.name(),
+        None => {
+            warn!(
+                "Entry for {} has unknown timezone {:?}; leaving it",
+                user_id, timezone
+            );
+            timezone
+        }
+    };
+
+    (new_username != username || new_timezone != timezone)
+        .then(|| (new_username.to_string(), new_timezone.to_string()))
+}
+
+/// Inserts the `SEED_TIMEZONE` record unless its user already has one, so an
+/// edited entry survives restarts. Panics on database errors, like the rest
+/// of startup.
+pub async fn seed(store: &dyn TimezoneStore, seed: Option<&SeedTimezone>) {
+    let Some(seed) = seed else {
+        return;
+    };
+
+    let existing = store
+        .get(&seed.user_id)
+        .await
+        .expect("Failed to read seed timezone entry");
+    if existing.is_some() {
+        return;
+    }
+
+    store
+        .set(TimezoneWrite {
+            user_id: &seed.user_id,
+            username: &seed.username,
+            timezone: seed.timezone.name(),
+            avatar: None,
+            replace_avatar: false,
+            time_format: None,
+            coordinates: None,
+        })
+        .await
+        .expect("Failed to store seed timezone entry");
+    info!(
+        "Seeded timezone {} for user {}",
+        seed.timezone.name(),
+        seed.user_id
+    );
+}
+
+/// The Postgres store. Bulk reads go to the replica; single-record reads and
+/// all writes go to the primary.
+pub struct PgTimezoneStore {
+    primary: PgPool,
+    replica: PgPool,
+}
+
+impl PgTimezoneStore {
+    pub fn new(primary: PgPool, replica: PgPool) -> Self {
+        PgTimezoneStore { primary, replica }
+    }
+}
+
+const COLUMNS: &str = "user_id, username, timezone, avatar, time_format, latitude, longitude";
+
+impl TimezoneStore for PgTimezoneStore {
+    fn get<'a>(&'a self, user_id: &'a str) -> StoreFuture<'a, Option<TimezoneRecord>> {
+        Box::pin(async move {
+            sqlx::query_as(&format!(
+                "SELECT {} FROM timezones WHERE user_id = $1",
+                COLUMNS
+            ))
+            .bind(user_id)
+            .fetch_optional(&self.primary)
+            .await
+        })
+    }
+
+    fn batch<'a>(&'a self, user_ids: &'a [String]) -> StoreFuture<'a, Vec<TimezoneRecord>> {
+        Box::pin(async move {
+            sqlx::query_as(&format!(
+                "SELECT {} FROM timezones WHERE user_id = ANY($1)",
+                COLUMNS
+            ))
+            .bind(user_ids)
+            .fetch_all(&self.replica)
+            .await
+        })
+    }
+
+    fn list(&self) -> StoreFuture<'_, Vec<TimezoneRecord>> {
+        Box::pin(async move {
+            sqlx::query_as(&format!(
+                "SELECT {} FROM timezones ORDER BY user_id",
+                COLUMNS
+            ))
+            .fetch_all(&self.replica)
+            .await
+        })
+    }
+
+    fn count(&self) -> StoreFuture<'_, i64> {
+        Box::pin(async move {
+            sqlx::query_scalar("SELECT COUNT(*) FROM timezones")
+                .fetch_one(&self.replica)
+                .await
+        })
+    }
+
+    fn same_zone<'a>(
+        &'a self,
+        timezone: &'a str,
+        except: &'a str,
+        after: &'a str,
+        limit: i64,
+    ) -> StoreFuture<'a, (Vec<TimezoneRecord>, i64)> {
+        Box::pin(async move {
+            let records = sqlx::query_as(&format!(
+                r#"
+                SELECT {} FROM timezones
+                WHERE timezone = $1 AND user_id <> $2 AND user_id > $3
+                ORDER BY user_id
+                LIMIT $4
+                "#,
+                COLUMNS
+            ))
+            .bind(timezone)
+            .bind(except)
+            .bind(after)
+            .bind(limit)
+            .fetch_all(&self.replica)
+            .await?;
+            let count = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM timezones WHERE timezone = $1 AND user_id <> $2",
+            )
+            .bind(timezone)
+            .bind(except)
+            .fetch_one(&self.replica)
+            .await?;
+            Ok((records, count))
+        })
+    }
+
+    fn export(&self) -> BoxStream<'_, Result<ExportRecord, sqlx::Error>> {
+        sqlx::query_as(
+            r#"
+            SELECT user_id, username, timezone, avatar, time_format, latitude, longitude,
+                to_char(updated_at AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"') AS updated_at
+            FROM timezones ORDER BY user_id
+            "#,
+        )
+        .fetch(&self.replica)
+    }
+
+    fn set<'a>(&'a self, write: TimezoneWrite<'a>) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            sqlx::query_scalar(
+                r#"
+                WITH previous AS (SELECT timezone FROM timezones WHERE user_id = $1)
+                INSERT INTO timezones
+                    (user_id, username, timezone, avatar, time_format, latitude, longitude)
+                VALUES ($1, $2, $3, $4, $6, $7, $8)
+                ON CONFLICT (user_id) DO UPDATE
+                SET username = EXCLUDED.username, timezone = EXCLUDED.timezone,
+                    avatar = CASE WHEN $5 THEN EXCLUDED.avatar ELSE timezones.avatar END,
+                    time_format = CASE WHEN $9 THEN $6 ELSE timezones.time_format END,
+                    latitude = CASE WHEN $10 THEN $7 ELSE timezones.latitude END,
+                    longitude = CASE WHEN $10 THEN $8 ELSE timezones.longitude END,
+                    updated_at = now()
+                RETURNING (SELECT timezone FROM previous) AS old_timezone
+                "#,
+            )
+            .bind(write.user_id)
+            .bind(write.username)
+            .bind(write.timezone)
+            .bind(write.avatar)
+            .bind(write.replace_avatar)
+            .bind(write.time_format.flatten())
+            .bind(write.coordinates.flatten().map(|c| c.lat))
+            .bind(write.coordinates.flatten().map(|c| c.lon))
+            .bind(write.time_format.is_some())
+            .bind(write.coordinates.is_some())
+            .fetch_one(&self.primary)
+            .await
+        })
+    }
+
+    fn delete<'a>(&'a self, user_id: &'a str) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let deleted: Option<String> =
+                sqlx::query_scalar("DELETE FROM timezones WHERE user_id = $1 RETURNING timezone")
+                    .bind(user_id)
+                    .fetch_optional(&self.primary)
+                    .await?;
+            // The row is already gone, so a failure here only costs /list
+            // clients one stale 304 and doesn't fail the delete.
+            if deleted.is_some() {
+                if let Err(err) = postgres::record_deletion(&self.primary).await {
+                    warn!("Failed to record deletion: {}", err);
+                }
+            }
+            Ok(deleted)
+        })
+    }
+
+    fn update_profile<'a>(
+        &'a self,
+        user_id: &'a str,
+        username: &'a str,
+        avatar: Option<&'a str>,
+    ) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let result = sqlx::query(
+                r#"
+                UPDATE timezones SET username = $2, avatar = $3, updated_at = now()
+                WHERE user_id = $1 AND (username IS DISTINCT FROM $2 OR avatar IS DISTINCT FROM $3)
+                "#,
+            )
+            .bind(user_id)
+            .bind(username)
+            .bind(avatar)
+            .execute(&self.primary)
+            .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    fn migrate<'a>(&'a self, version: &'a str, fix: &'a EntryFix) -> StoreFuture<'a, Option<u64>> {
+        Box::pin(async move {
+            let mut tx = self.primary.begin().await?;
+            let claimed = sqlx::query(
+                "INSERT INTO schema_migrations (version) VALUES ($1) ON CONFLICT DO NOTHING",
+            )
+            .bind(version)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+                > 0;
+            if !claimed {
+                return Ok(None);
+            }
+
+            let mut changed = 0;
+            let mut after = String::new();
+            loop {
+                let batch: Vec<(String, String, String)> = sqlx::query_as(
+                    r#"
+                    SELECT user_id, username, timezone FROM timezones
+                    WHERE user_id > $1 ORDER BY user_id LIMIT $2
+                    "#,
+                )
+                .bind(&after)
+                .bind(MIGRATION_BATCH)
+                .fetch_all(&mut *tx)
+                .await?;
+                let Some((last, _, _)) = batch.last() else {
+                    break;
+                };
+                after = last.clone();
+
+                for (user_id, username, timezone) in &batch {
+                    if let Some((username, timezone)) = fix(user_id, username, timezone) {
+                        sqlx::query(
+                            "UPDATE timezones SET username = $2, timezone = $3 WHERE user_id = $1",
+                        )
+                        .bind(user_id)
+                        .bind(username)
+                        .bind(timezone)
+                        .execute(&mut *tx)
+                        .await?;
+                        changed += 1;
+                    }
+                }
+            }
+            tx.commit().await?;
+            Ok(Some(changed))
+        })
+    }
+
+    fn merge<'a>(
+        &'a self,
+        from: &'a str,
+        to: &'a str,
+        on_conflict: MergeConflict,
+    ) -> StoreFuture<'a, MergeOutcome> {
+        Box::pin(async move {
+            let mut tx = self.primary.begin().await?;
+
+            let source = sqlx::query("SELECT 1 FROM timezones WHERE user_id = $1 FOR UPDATE")
+                .bind(from)
+                .fetch_optional(&mut *tx)
+                .await?;
+            if source.is_none() {
+                return Ok(MergeOutcome::SourceMissing);
+            }
+
+            let target = sqlx::query("SELECT 1 FROM timezones WHERE user_id = $1 FOR UPDATE")
+                .bind(to)
+                .fetch_optional(&mut *tx)
+                .await?;
+
+            let outcome = match (target.is_some(), on_conflict) {
+                (true, MergeConflict::Error) => return Ok(MergeOutcome::TargetExists),
+                (true, MergeConflict::KeepTarget) => {
+                    sqlx::query("DELETE FROM timezones WHERE user_id = $1")
+                        .bind(from)
+                        .execute(&mut *tx)
+                        .await?;
+                    postgres::record_deletion(&mut *tx).await?;
+                    MergeOutcome::KeptTarget
+                }
+                (true, MergeConflict::KeepSource) => {
+                    sqlx::query("DELETE FROM timezones WHERE user_id = $1")
+                        .bind(to)
+                        .execute(&mut *tx)
+                        .await?;
+                    rename(&mut tx, from, to).await?;
+                    MergeOutcome::ReplacedTarget
+                }
+                (false, _) => {
+                    rename(&mut tx, from, to).await?;
+                    MergeOutcome::Merged
+                }
+            };
+
+            tx.commit().await?;
+            Ok(outcome)
+        })
+    }
+
+    fn remap<'a>(&'a self, from: &'a str, to: &'a str) -> StoreFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            sqlx::query_scalar(
+                "UPDATE timezones SET timezone = $2, updated_at = now() WHERE timezone = $1 RETURNING user_id",
+            )
+            .bind(from)
+            .bind(to)
+            .fetch_all(&self.primary)
+            .await
+        })
+    }
+
+    fn last_modified(&self) -> StoreFuture<'_, Option<i64>> {
+        Box::pin(postgres::last_modified(&self.replica))
+    }
+
+    fn ping(&self) -> StoreFuture<'_, ()> {
+        Box::pin(async move {
+            sqlx::query("SELECT 1")
+                .execute(&self.primary)
+                .await
+                .map(drop)
+        })
+    }
+
+    fn pool_size(&self) -> u32 {
+        self.primary.size()
+    }
+}
+
+impl KeyStore for PgTimezoneStore {
+    fn find_key<'a>(
+        &'a self,
+        key_hash: &'a str,
+    ) -> StoreFuture<'a, Option<(String, String, String)>> {
+        Box::pin(async move {
+            sqlx::query_as(
+                "SELECT id, name, scope FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+            )
+            .bind(key_hash)
+            .fetch_optional(&self.primary)
+            .await
+        })
+    }
+
+    fn list_keys(&self) -> StoreFuture<'_, Vec<KeyRecord>> {
+        Box::pin(async move {
+            let rows: Vec<(String, String, String, String, bool)> = sqlx::query_as(
+                r#"
+                SELECT id, name, scope, created_at::TEXT, revoked_at IS NOT NULL
+                FROM api_keys
+                ORDER BY created_at
+                "#,
+            )
+            .fetch_all(&self.primary)
+            .await?;
+            Ok(rows.into_iter().map(KeyRecord::from).collect())
+        })
+    }
+
+    fn insert_key<'a>(
+        &'a self,
+        id: &'a str,
+        name: &'a str,
+        key_hash: &'a str,
+        scope: &'a str,
+    ) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let result = sqlx::query(
+                r#"
+                INSERT INTO api_keys (id, name, key_hash, scope) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (key_hash) DO NOTHING
+                "#,
+            )
+            .bind(id)
+            .bind(name)
+            .bind(key_hash)
+            .bind(scope)
+            .execute(&self.primary)
+            .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    fn revoke_key<'a>(&'a self, id: &'a str) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let result = sqlx::query(
+                "UPDATE api_keys SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL",
+            )
+            .bind(id)
+            .execute(&self.primary)
+            .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+}
+
+impl From<(String, String, String, String, bool)> for KeyRecord {
+    fn from(
+        (id, name, scope, created_at, revoked): (String, String, String, String, bool),
+    ) -> Self {
+        KeyRecord {
+            id,
+            name,
+            scope,
+            created_at,
+            revoked,
+        }
+    }
+}
+
+async fn rename(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    from: &str,
+    to: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE timezones SET user_id = $2, updated_at = now() WHERE user_id = $1")
+        .bind(from)
+        .bind(to)
+        .execute(&mut **tx)
+        .await
+        .map(drop)
+}
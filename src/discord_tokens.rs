@@ -0,0 +1,141 @@
+//! Encrypted storage of a session's Discord OAuth tokens, for features that
+//! call Discord on the user's behalf after login. Only active with
+//! `STORE_DISCORD_TOKENS=true`.
+
+use crate::clock::unix_now;
+use crate::db::AppState;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Tokens this close to expiry are refreshed before use.
+const REFRESH_MARGIN: u64 = 60;
+
+#[derive(Serialize, Deserialize)]
+pub struct DiscordTokens {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// Unix time the access token stops working.
+    pub expires_at: u64,
+}
+
+impl DiscordTokens {
+    /// Reads a token endpoint response; `None` without an access token.
+    pub fn from_response(json: &serde_json::Value) -> Option<Self> {
+        Some(DiscordTokens {
+            access_token: json["access_token"].as_str()?.to_string(),
+            refresh_token: json["refresh_token"].as_str().map(String::from),
+            expires_at: unix_now() + json["expires_in"].as_u64().unwrap_or_default(),
+        })
+    }
+}
+
+/// The Redis key a session's tokens are kept under.
+pub fn storage_key(session_id: &str) -> String {
+    format!("discord_tokens:{}", session_id)
+}
+
+/// Encrypts `tokens` with AES-256-GCM and stores them next to the session,
+/// expiring with it. Does nothing unless token storage is enabled; failures
+/// are logged and never fail the login.
+pub async fn store(state: &AppState, session_id: &str, tokens: &DiscordTokens, ttl: u64) {
+    let Some(secret) = state.config.discord_token_key else {
+        return;
+    };
+    let Some(sealed) = seal(&secret, session_id, tokens) else {
+        warn!("Failed to encrypt Discord tokens");
+        return;
+    };
+    if let Err(err) = state
+        .kv
+        .set_ex(&storage_key(session_id), &sealed, ttl)
+        .await
+    {
+        warn!("Failed to store Discord tokens: {}", err);
+    }
+}
+
+/// A usable access token for the session, refreshing it through the refresh
+/// grant when it has expired. `None` when no tokens are stored or Discord
+/// won't renew them.
+pub async fn access_token(state: &AppState, session_id: &str) -> Option<String> {
+    let secret = state.config.discord_token_key?;
+    let key = storage_key(session_id);
+    let sealed = state.kv.get(&key).await.ok()?;
+    let tokens = open(&secret, session_id, &sealed?)?;
+    if tokens.expires_at > unix_now() + REFRESH_MARGIN {
+        return Some(tokens.access_token);
+    }
+
+    let refreshed = refresh(state, tokens.refresh_token.as_deref()?).await?;
+    let ttl = state.kv.ttl(&key).await.unwrap_or(-1);
+    if let Ok(ttl) = u64::try_from(ttl) {
+        store(state, session_id, &refreshed, ttl.max(1)).await;
+    }
+    Some(refreshed.access_token)
+}
+
+async fn refresh(state: &AppState, refresh_token: &str) -> Option<DiscordTokens> {
+    let discord = &state.config.discord;
+    let form = [
+        ("client_id", discord.client_id.as_str()),
+        ("client_secret", discord.client_secret.as_str()),
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+    ];
+    let res = state
+        .http
+        .post("https://discord.com/api/oauth2/token")
+        .form(&form)
+        .send()
+        .await;
+    let json = match res {
+        Ok(res) if res.status().is_success() => res.json::<serde_json::Value>().await.ok()?,
+        Ok(res) => {
+            warn!("Discord token refresh was rejected: {}", res.status());
+            return None;
+        }
+        Err(err) => {
+            warn!("Discord token refresh failed: {}", err);
+            return None;
+        }
+    };
+    DiscordTokens::from_response(&json)
+}
+
+/// `base64(nonce || ciphertext || tag)`. The session ID is authenticated as
+/// associated data, so a blob copied under another session fails to open.
+fn seal(secret: &[u8; 32], session_id: &str, tokens: &DiscordTokens) -> Option<String> {
+    let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, secret).ok()?);
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).ok()?;
+    let mut data = serde_json::to_vec(tokens).ok()?;
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(session_id),
+        &mut data,
+    )
+    .ok()?;
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&data);
+    Some(STANDARD.encode(out))
+}
+
+fn open(secret: &[u8; 32], session_id: &str, sealed: &str) -> Option<DiscordTokens> {
+    let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, secret).ok()?);
+    let raw = STANDARD.decode(sealed).ok()?;
+    if raw.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, data) = raw.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+    let mut data = data.to_vec();
+    let plain = key
+        .open_in_place(nonce, Aad::from(session_id), &mut data)
+        .ok()?;
+    serde_json::from_slice(plain).ok()
+}
@@ -0,0 +1,168 @@
+use crate::types::JsonMessage;
+use axum::{
+    extract::rejection::{FormRejection, JsonRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::fmt;
+use tracing::error;
+
+/// Everything a handler can fail with. Each variant maps to one status code
+/// and a `JsonMessage` body; server-side failures are logged here with their
+/// cause, so handlers can use `?` without logging at every call site.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    UnsupportedMediaType(String),
+    TooManyRequests(String),
+    /// The server is overloaded or a dependency is switched off; nothing to
+    /// log.
+    Unavailable(String),
+    /// Stored data the server can't make sense of.
+    Internal(String),
+    /// An extractor rejected the request, with the status it chose.
+    Rejected(StatusCode, String),
+    /// A query failed. Clients only see `message`, or a 503 when Postgres
+    /// cancelled it for running past `DB_STATEMENT_TIMEOUT`.
+    Db {
+        message: &'static str,
+        source: sqlx::Error,
+    },
+    /// Redis failed. Reported as 503, since sessions and state live there.
+    Redis {
+        message: &'static str,
+        source: redis::RedisError,
+    },
+    /// Discord or another upstream failed or answered with something unusable.
+    External {
+        message: &'static str,
+        detail: String,
+    },
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        AppError::BadRequest(message.into())
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        AppError::Unauthorized(message.into())
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        AppError::Forbidden(message.into())
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        AppError::NotFound(message.into())
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        AppError::Conflict(message.into())
+    }
+
+    /// For `map_err` on queries whose failure deserves its own message.
+    pub fn db(message: &'static str) -> impl FnOnce(sqlx::Error) -> Self {
+        move |source| AppError::Db { message, source }
+    }
+
+    /// For `map_err` on outbound calls; `detail` is logged, never returned.
+    pub fn external<E: fmt::Display>(message: &'static str) -> impl FnOnce(E) -> Self {
+        move |detail| AppError::External {
+            message,
+            detail: detail.to_string(),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(source: sqlx::Error) -> Self {
+        AppError::Db {
+            message: "Database error",
+            source,
+        }
+    }
+}
+
+impl From<redis::RedisError> for AppError {
+    fn from(source: redis::RedisError) -> Self {
+        AppError::Redis {
+            message: "Session store unavailable",
+            source,
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::Rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<FormRejection> for AppError {
+    fn from(rejection: FormRejection) -> Self {
+        AppError::Rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::Rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+/// SQLSTATE 57014, `query_canceled`, which is what `statement_timeout` raises.
+fn is_statement_timeout(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .and_then(|db| db.code())
+        .is_some_and(|code| code == "57014")
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            AppError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            AppError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message),
+            AppError::Forbidden(message) => (StatusCode::FORBIDDEN, message),
+            AppError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            AppError::Conflict(message) => (StatusCode::CONFLICT, message),
+            AppError::UnsupportedMediaType(message) => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, message)
+            }
+            AppError::TooManyRequests(message) => (StatusCode::TOO_MANY_REQUESTS, message),
+            AppError::Unavailable(message) => (StatusCode::SERVICE_UNAVAILABLE, message),
+            AppError::Rejected(status, message) => (status, message),
+            AppError::Internal(message) => {
+                error!("{}", message);
+                (StatusCode::INTERNAL_SERVER_ERROR, message)
+            }
+            AppError::Db { message, source } if is_statement_timeout(&source) => {
+                error!("{}: {}", message, source);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Database query timed out".into(),
+                )
+            }
+            AppError::Db { message, source } => {
+                error!("{}: {}", message, source);
+                (StatusCode::INTERNAL_SERVER_ERROR, message.into())
+            }
+            AppError::Redis { message, source } => {
+                error!("{}: {}", message, source);
+                (StatusCode::SERVICE_UNAVAILABLE, message.into())
+            }
+            AppError::External { message, detail } => {
+                error!("{}: {}", message, detail);
+                (StatusCode::BAD_GATEWAY, message.into())
+            }
+        };
+        (status, Json(JsonMessage { message })).into_response()
+    }
+}
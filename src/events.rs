@@ -0,0 +1,33 @@
+use crate::webhook::TimezoneEvent;
+use tokio::sync::{broadcast, watch};
+
+/// Events held for subscribers that fall behind; past this they skip ahead
+/// and are told how many they missed.
+const BUFFERED_EVENTS: usize = 64;
+
+/// Fans timezone changes out to `/events` subscribers. Publishing never
+/// waits, with or without subscribers.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<TimezoneEvent>,
+    shutdown: watch::Receiver<()>,
+}
+
+impl EventBus {
+    /// `shutdown` changing ends every open stream, so graceful shutdown
+    /// doesn't wait on them forever.
+    pub fn new(shutdown: watch::Receiver<()>) -> Self {
+        let (sender, _) = broadcast::channel(BUFFERED_EVENTS);
+        EventBus { sender, shutdown }
+    }
+
+    pub fn publish(&self, event: &TimezoneEvent) {
+        if self.sender.receiver_count() > 0 {
+            self.sender.send(event.clone()).ok();
+        }
+    }
+
+    pub fn subscribe(&self) -> (broadcast::Receiver<TimezoneEvent>, watch::Receiver<()>) {
+        (self.sender.subscribe(), self.shutdown.clone())
+    }
+}
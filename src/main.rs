@@ -1,48 +1,249 @@
-use axum::{serve, Router};
+use axum::{
+    extract::Request,
+    middleware::{from_fn, from_fn_with_state, map_request_with_state, map_response},
+    serve, Router, ServiceExt,
+};
 use dotenvy::dotenv;
-use std::net::SocketAddr;
-use tokio::net::TcpListener;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::{
+    net::TcpListener,
+    sync::{watch, Semaphore},
+    task::JoinSet,
+};
+use tower::Layer;
 use tracing::{error, info};
-use tracing_subscriber;
 
+mod clock;
+mod config;
 mod db;
+mod discord_tokens;
+mod error;
+mod events;
+mod metrics;
 mod middleware;
 mod routes;
 mod types;
+mod tz;
+mod webhook;
 
-use db::{postgres, redis_helper, AppState};
-use middleware::cors::DynamicCors;
+use config::{Config, FieldCase, LogFormat, StartupStats};
+use db::{kv::Kv, redis_helper, store, AppState};
+use middleware::{
+    api_key, compression,
+    cors::DynamicCors,
+    errors::{json_errors, negotiate_messages},
+    field_case::camel_case_fields,
+    inflight::track_in_flight,
+    limit::limit_concurrency,
+    msgpack::negotiate_msgpack,
+    path::trim_trailing_slash,
+};
+
+/// How often the remaining request count is logged during shutdown.
+const DRAIN_LOG_INTERVAL: Duration = Duration::from_secs(1);
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    tracing_subscriber::fmt::init();
+    match config::log_format() {
+        LogFormat::Full => tracing_subscriber::fmt().init(),
+        LogFormat::Compact => tracing_subscriber::fmt().compact().init(),
+        LogFormat::Pretty => tracing_subscriber::fmt().pretty().init(),
+    }
+
+    if config::check_requested() {
+        check_config();
+    }
+
+    let config = match Config::from_env() {
+        Ok(config) => Arc::new(config),
+        Err(err) => {
+            error!("Invalid configuration: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let http = match config.http_client() {
+        Ok(http) => http,
+        Err(err) => {
+            error!("Invalid configuration: {}", err);
+            std::process::exit(1);
+        }
+    };
 
-    let db = postgres::connect().await;
-    let redis = redis_helper::connect().await;
-    let state = AppState { db, redis };
+    let (store, keys) = store::connect(&config).await;
+    let kv = redis_helper::connect(&config).await;
+    api_key::ensure_bootstrap_key(keys.as_ref(), config.bootstrap_admin_key.as_deref()).await;
+
+    if let Err(err) = store::normalize(store.as_ref()).await {
+        error!(
+            "Failed to normalize stored entries, retrying on next start: {}",
+            err
+        );
+    }
+    store::seed(store.as_ref(), config.seed_timezone.as_ref()).await;
+
+    let stats = StartupStats {
+        db_pool_size: store.pool_size(),
+        redis_connections: u32::from(matches!(kv, Kv::Redis(_))),
+    };
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let state = AppState {
+        store,
+        keys,
+        kv,
+        http,
+        config: config.clone(),
+        cors_origins: Arc::new(RwLock::new(config.cors_allowed_origins.clone())),
+        events: events::EventBus::new(shutdown_rx.clone()),
+    };
 
     let app = Router::new()
-        .merge(routes::all())
+        .merge(routes::all(&config))
+        .layer(from_fn_with_state(state.clone(), api_key::authenticate))
         .with_state(state.clone())
-        .layer(DynamicCors);
+        .layer(map_response(json_errors));
+    let app = match config.api_field_case {
+        FieldCase::Camel => app.layer(map_response(camel_case_fields)),
+        FieldCase::Snake => app,
+    };
+    let app = app
+        .layer(from_fn(negotiate_messages))
+        .layer(from_fn(negotiate_msgpack));
+    let app = match config.max_concurrent_requests {
+        Some(max) => app.layer(from_fn_with_state(
+            Arc::new(Semaphore::new(max)),
+            limit_concurrency,
+        )),
+        None => app,
+    };
+    let app = app
+        .layer(DynamicCors::new(
+            state.cors_origins.clone(),
+            config.cors_any_origin,
+        ))
+        .layer(from_fn(track_in_flight));
+    let app = match &config.compression {
+        Some(settings) => app
+            .layer(compression::layer(settings))
+            .layer(map_request_with_state(
+                Arc::new(settings.clone()),
+                compression::prefer_encoding,
+            )),
+        None => app,
+    };
+    let app = map_request_with_state(config.clone(), trim_trailing_slash).layer(app);
 
-    let host = std::env::var("HOST").unwrap_or_else(|_| "0.0.0.0".into());
-    let port: u16 = std::env::var("PORT")
-        .unwrap_or_else(|_| "3000".to_string())
-        .parse()
-        .expect("PORT must be a number");
+    let mut listeners = Vec::new();
+    for addr in &config.server.addrs {
+        match TcpListener::bind(addr).await {
+            Ok(listener) => listeners.push((*addr, listener)),
+            Err(err) => {
+                error!("Failed to bind {}: {}", addr, err);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let addr = format!("{}:{}", host, port)
-        .parse::<SocketAddr>()
-        .expect("Invalid HOST or PORT");
+    config.log_startup_summary(&stats);
 
-    let listener = TcpListener::bind(addr)
-        .await
-        .expect("Failed to bind address");
+    let mut servers = JoinSet::new();
+    for (addr, listener) in listeners {
+        let app = app.clone();
+        let mut shutdown = shutdown_rx.clone();
+        info!("Listening on http://{}", addr);
+        servers.spawn(async move {
+            let result = serve(listener, ServiceExt::<Request>::into_make_service(app))
+                .with_graceful_shutdown(async move {
+                    shutdown.changed().await.ok();
+                })
+                .await;
+            if let Err(err) = result {
+                error!("Server error on {}: {}", addr, err);
+            }
+        });
+    }
+    if let Some(interval) = config.zone_check_interval {
+        servers.spawn(metrics::check_stored_zones(
+            state.store.clone(),
+            interval,
+            shutdown_rx.clone(),
+        ));
+    }
+    servers.spawn(metrics::track_user_count(state.store.clone(), shutdown_rx));
+
+    shutdown_signal().await;
+    info!(
+        "Shutting down with {} requests in flight",
+        metrics::REQUESTS_IN_FLIGHT.get()
+    );
+    shutdown_tx.send(()).ok();
+    drain(servers).await;
+}
+
+/// Waits for every server to finish its in-flight requests, logging how many
+/// remain every `DRAIN_LOG_INTERVAL` while it does.
+async fn drain(servers: JoinSet<()>) {
+    let done = servers.join_all();
+    tokio::pin!(done);
+    let mut ticks = tokio::time::interval(DRAIN_LOG_INTERVAL);
+    ticks.tick().await;
+    loop {
+        tokio::select! {
+            _ = &mut done => break,
+            _ = ticks.tick() => info!(
+                "Draining: {} requests remaining",
+                metrics::REQUESTS_IN_FLIGHT.get()
+            ),
+        }
+    }
+    info!("All requests drained");
+}
+
+/// Resolves on Ctrl+C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.ok();
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
 
-    info!("Listening on http://{}", addr);
-    if let Err(err) = serve(listener, app).await {
-        error!("Server error: {}", err);
+/// Prints the resolved configuration and exits 0, or prints the first problem
+/// and exits 1. Never touches Postgres, Redis or the listener.
+fn check_config() -> ! {
+    match Config::from_env() {
+        Ok(config) => {
+            if let Err(err) = config.http_client() {
+                eprintln!("Invalid configuration: {}", err);
+                std::process::exit(1);
+            }
+            for (key, value) in config.redacted() {
+                println!("{} = {}", key, value);
+            }
+            println!("Configuration OK");
+            std::process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("Invalid configuration: {}", err);
+            std::process::exit(1);
+        }
     }
 }
@@ -0,0 +1,285 @@
+use crate::db::store::TimezoneStore;
+use crate::tz;
+use chrono_tz::Tz;
+use futures_util::StreamExt;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::warn;
+
+/// How often `TIMEZONES_TOTAL` is refreshed from the database.
+const USER_COUNT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A monotonically increasing process-wide counter.
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Counter {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Started logins. Far more created than consumed states can indicate
+/// scanning of the login endpoint.
+pub static OAUTH_STATES_CREATED: Counter = Counter::new(
+    "oauth_states_created_total",
+    "OAuth state tokens issued by /auth/discord",
+);
+pub static OAUTH_STATES_CONSUMED: Counter = Counter::new(
+    "oauth_states_consumed_total",
+    "OAuth state tokens redeemed by the callback",
+);
+
+static COUNTERS: &[&Counter] = &[&OAUTH_STATES_CREATED, &OAUTH_STATES_CONSUMED];
+
+/// A process-wide value that is overwritten rather than incremented.
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Gauge {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Gauge {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set(&self, value: u64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.value.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Stored users, refreshed in the background so scrapes never run the count.
+pub static TIMEZONES_TOTAL: Gauge = Gauge::new("timezones_total", "Users with a stored timezone");
+
+/// Requests received but not yet answered; logged while draining on shutdown.
+pub static REQUESTS_IN_FLIGHT: Gauge = Gauge::new(
+    "http_requests_in_flight",
+    "Requests currently being handled",
+);
+
+/// Stored entries whose zone the bundled tz database doesn't know, as of the
+/// last `check_stored_zones` scan.
+pub static TIMEZONES_INVALID: Gauge = Gauge::new(
+    "timezones_invalid",
+    "Stored timezones that no longer parse, as of the last check",
+);
+
+static GAUGES: &[&Gauge] = &[&TIMEZONES_TOTAL, &REQUESTS_IN_FLIGHT, &TIMEZONES_INVALID];
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// Durations sorted into `LATENCY_BUCKETS`, with a running sum and count.
+pub struct Histogram {
+    /// Non-cumulative per bucket; `render` adds them up.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS.len()],
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|le| secs <= *le) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Top-level zone areas used as the `region` label. Everything else (`UTC`,
+/// `Factory`, anything new) counts as `other`, so the label can't grow with
+/// the data.
+const REGIONS: [&str; 12] = [
+    "Africa",
+    "America",
+    "Antarctica",
+    "Arctic",
+    "Asia",
+    "Atlantic",
+    "Australia",
+    "Etc",
+    "Europe",
+    "Indian",
+    "Pacific",
+    "other",
+];
+
+const ZONE_LATENCY_NAME: &str = "zone_request_duration_seconds";
+const ZONE_LATENCY_HELP: &str =
+    "Time to answer per-user time requests, by the top-level region of the user's zone";
+
+static ZONE_LATENCY: [Histogram; REGIONS.len()] = [const { Histogram::new() }; REGIONS.len()];
+
+/// Records how long a per-user time endpoint took for a user in `tz`. Legacy
+/// names count towards their canonical zone's region, so `US/Eastern` is
+/// `America`.
+pub fn observe_zone_latency(tz: Tz, elapsed: Duration) {
+    let name = tz::canonical(tz).name();
+    let region = name.split_once('/').map_or("other", |(area, _)| area);
+    let i = REGIONS
+        .iter()
+        .position(|known| *known == region)
+        .unwrap_or(REGIONS.len() - 1);
+    ZONE_LATENCY[i].observe(elapsed);
+}
+
+/// Refreshes `TIMEZONES_TOTAL` every `USER_COUNT_INTERVAL` until `shutdown`
+/// fires. A failed count keeps the previous value.
+pub async fn track_user_count(store: Arc<dyn TimezoneStore>, mut shutdown: watch::Receiver<()>) {
+    let mut ticks = tokio::time::interval(USER_COUNT_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = shutdown.changed() => return,
+        }
+        match store.count().await {
+            Ok(count) => TIMEZONES_TOTAL.set(count.max(0) as u64),
+            Err(err) => warn!("Failed to count stored users: {}", err),
+        }
+    }
+}
+
+/// Parses every stored zone each `interval` until `shutdown`, logging the
+/// entries whose zone no longer exists (typically after a tz database
+/// upgrade) so they can be fixed with `/admin/remap` before their users notice.
+/// Rows are read through the export cursor, so the table is never loaded at
+/// once.
+pub async fn check_stored_zones(
+    store: Arc<dyn TimezoneStore>,
+    interval: Duration,
+    mut shutdown: watch::Receiver<()>,
+) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = shutdown.changed() => return,
+        }
+
+        let mut rows = store.export();
+        let mut invalid = 0;
+        let mut failed = false;
+        while let Some(row) = rows.next().await {
+            match row {
+                Ok(row) if row.record.timezone.parse::<Tz>().is_err() => {
+                    warn!(
+                        "Stored timezone {:?} for {} no longer parses",
+                        row.record.timezone, row.record.user_id
+                    );
+                    invalid += 1;
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!("Failed to check stored timezones: {}", err);
+                    failed = true;
+                    break;
+                }
+            }
+        }
+        // A partial scan would undercount, so the last full result stands.
+        if failed {
+            continue;
+        }
+        TIMEZONES_INVALID.set(invalid);
+        if invalid > 0 {
+            warn!(
+                "{} stored timezones no longer parse; see /admin/remap",
+                invalid
+            );
+        }
+    }
+}
+
+/// Renders every metric in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    for counter in COUNTERS {
+        let _ = writeln!(out, "# HELP {} {}", counter.name, counter.help);
+        let _ = writeln!(out, "# TYPE {} counter", counter.name);
+        let _ = writeln!(
+            out,
+            "{} {}",
+            counter.name,
+            counter.value.load(Ordering::Relaxed)
+        );
+    }
+    for gauge in GAUGES {
+        let _ = writeln!(out, "# HELP {} {}", gauge.name, gauge.help);
+        let _ = writeln!(out, "# TYPE {} gauge", gauge.name);
+        let _ = writeln!(out, "{} {}", gauge.name, gauge.get());
+    }
+
+    let _ = writeln!(out, "# HELP {} {}", ZONE_LATENCY_NAME, ZONE_LATENCY_HELP);
+    let _ = writeln!(out, "# TYPE {} histogram", ZONE_LATENCY_NAME);
+    for (region, histogram) in REGIONS.iter().zip(&ZONE_LATENCY) {
+        let mut cumulative = 0;
+        for (le, bucket) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "{}_bucket{{region=\"{}\",le=\"{}\"}} {}",
+                ZONE_LATENCY_NAME, region, le, cumulative
+            );
+        }
+        let count = histogram.count.load(Ordering::Relaxed);
+        let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(
+            out,
+            "{}_bucket{{region=\"{}\",le=\"+Inf\"}} {}",
+            ZONE_LATENCY_NAME, region, count
+        );
+        let _ = writeln!(
+            out,
+            "{}_sum{{region=\"{}\"}} {}",
+            ZONE_LATENCY_NAME, region, sum
+        );
+        let _ = writeln!(
+            out,
+            "{}_count{{region=\"{}\"}} {}",
+            ZONE_LATENCY_NAME, region, count
+        );
+    }
+    out
+}
@@ -0,0 +1,150 @@
+use crate::db::{store::KeyStore, AppState};
+use crate::error::{AppError, AppResult};
+use axum::{
+    extract::{Request, State},
+    http::header::AUTHORIZATION,
+    middleware::Next,
+    response::Response,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiScope {
+    /// Required by nothing: every read endpoint is public, so a read key
+    /// unlocks nothing a caller without one can't already do. Read-only
+    /// integrations get one anyway, so they stay read-only should some read
+    /// ever need a key.
+    Read,
+    Write,
+    Admin,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Write => "write",
+            ApiScope::Admin => "admin",
+        }
+    }
+
+    /// Scopes are hierarchical: admin implies write, write implies read.
+    pub fn allows(&self, required: ApiScope) -> bool {
+        *self >= required
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(ApiScope::Read),
+            "write" => Ok(ApiScope::Write),
+            "admin" => Ok(ApiScope::Admin),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The API key that authenticated the current request, attached as a request
+/// extension by [`authenticate`].
+#[derive(Clone, Debug)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scope: ApiScope,
+}
+
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("tzdb_{}", hex::encode(bytes))
+}
+
+/// Keys are stored as a plain SHA-256 digest rather than an Argon2 hash. A
+/// slow, salted KDF protects guessable passwords; `generate_key` draws 256
+/// random bits, so its keys can't be guessed from the digest any faster than
+/// from nothing, and `BOOTSTRAP_ADMIN_KEY` has a minimum length for the same
+/// reason. No Argon2 implementation is built into this service either. An
+/// unsalted digest also lets `authenticate` find the key by index instead of
+/// verifying against every row.
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+pub async fn authenticate(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> AppResult<Response> {
+    let Some(header) = req.headers().get(AUTHORIZATION) else {
+        return Ok(next.run(req).await);
+    };
+
+    // Other schemes (say, Basic auth added by a proxy in front) aren't ours
+    // to judge; public routes serve them, and protected ones answer 401 for
+    // the missing key.
+    let Some(key) = header
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return Ok(next.run(req).await);
+    };
+
+    let (id, name, scope) = state
+        .keys
+        .find_key(&hash_key(key.trim()))
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Invalid API key"))?;
+
+    let scope = scope
+        .parse::<ApiScope>()
+        .map_err(|_| AppError::unauthorized("Invalid API key scope"))?;
+
+    req.extensions_mut().insert(ApiKey { id, name, scope });
+
+    Ok(next.run(req).await)
+}
+
+/// Rejects the request unless it was authenticated with a key holding at
+/// least `required`.
+pub fn require_scope(api_key: Option<&ApiKey>, required: ApiScope) -> AppResult<&ApiKey> {
+    match api_key {
+        Some(key) if key.scope.allows(required) => Ok(key),
+        Some(_) => Err(AppError::forbidden(format!(
+            "API key lacks {} scope",
+            required.as_str()
+        ))),
+        None => Err(AppError::unauthorized("Missing API key")),
+    }
+}
+
+/// Registers `BOOTSTRAP_ADMIN_KEY` as an admin key so a fresh deployment has a
+/// way to create its first real keys.
+pub async fn ensure_bootstrap_key(keys: &dyn KeyStore, key: Option<&str>) {
+    let Some(key) = key else {
+        return;
+    };
+
+    let added = keys
+        .insert_key(
+            &Uuid::now_v7().to_string(),
+            "bootstrap",
+            &hash_key(key.trim()),
+            ApiScope::Admin.as_str(),
+        )
+        .await
+        .expect("Failed to register bootstrap admin key");
+
+    if added {
+        info!("Registered bootstrap admin API key");
+    }
+}
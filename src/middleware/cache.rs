@@ -0,0 +1,17 @@
+use axum::{
+    http::{
+        header::{CACHE_CONTROL, PRAGMA},
+        HeaderValue,
+    },
+    response::Response,
+};
+
+/// Marks a response as per-user so shared caches never store it. Only applied
+/// to session and API-key routes; public reads and static assets keep their
+/// own caching headers.
+pub async fn no_store(mut res: Response) -> Response {
+    let headers = res.headers_mut();
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(PRAGMA, HeaderValue::from_static("no-cache"));
+    res
+}
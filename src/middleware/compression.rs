@@ -0,0 +1,72 @@
+use crate::config::{CompressionAlgorithm, CompressionConfig};
+use axum::{
+    extract::{Request, State},
+    http::{header::ACCEPT_ENCODING, HeaderValue},
+};
+use std::sync::Arc;
+use tower_http::compression::{
+    predicate::{NotForContentType, Predicate},
+    CompressionLayer, DefaultPredicate,
+};
+
+/// Compression buffers output, which would hold back the streamed JSON Lines
+/// list that is meant to reach clients as it is read.
+pub fn layer(config: &CompressionConfig) -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new()
+        .quality(config.level)
+        .gzip(config.algorithms.contains(&CompressionAlgorithm::Gzip))
+        .deflate(config.algorithms.contains(&CompressionAlgorithm::Deflate))
+        .compress_when(
+            DefaultPredicate::new().and(NotForContentType::const_new("application/x-ndjson")),
+        )
+}
+
+/// Narrows `Accept-Encoding` to the operator's most preferred algorithm that
+/// the client accepts. `CompressionLayer` breaks ties between equally
+/// weighted encodings in a fixed order of its own, so the choice is made here
+/// instead. Must run before the compression layer sees the request.
+pub async fn prefer_encoding(
+    State(config): State<Arc<CompressionConfig>>,
+    mut req: Request,
+) -> Request {
+    let Some(accept) = req
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return req;
+    };
+
+    let preferred = config
+        .algorithms
+        .iter()
+        .find(|algorithm| accepts(accept, algorithm.as_str()));
+    if let Some(algorithm) = preferred {
+        req.headers_mut().insert(
+            ACCEPT_ENCODING,
+            HeaderValue::from_static(algorithm.as_str()),
+        );
+    }
+    req
+}
+
+/// Whether `accept` allows `encoding`, explicitly or through `*`, with a
+/// non-zero q-value. An explicit entry wins over the wildcard.
+fn accepts(accept: &str, encoding: &str) -> bool {
+    let mut wildcard = false;
+    for item in accept.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let q = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(encoding) {
+            return q > 0.0;
+        }
+        if name == "*" {
+            wildcard = q > 0.0;
+        }
+    }
+    wildcard
+}
@@ -1,25 +1,66 @@
-use axum::http::{HeaderValue, Request, Response};
+use super::add_vary;
+use axum::http::{
+    header::{
+        ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ORIGIN,
+    },
+    HeaderValue, Method, Request, Response,
+};
 use std::{
     future::Future,
     pin::Pin,
+    sync::{Arc, RwLock},
     task::{Context, Poll},
 };
 use tower::{Layer, Service};
 
+/// Origins allowed to make credentialed requests. Empty means any origin is
+/// reflected, unless `CORS_ALLOW_ANY_ORIGIN` is off. Shared with `AppState`
+/// so admins can replace it at runtime.
+pub type CorsOrigins = Arc<RwLock<Vec<String>>>;
+
 #[derive(Clone)]
-pub struct DynamicCors;
+pub struct DynamicCors {
+    origins: CorsOrigins,
+    any_when_empty: bool,
+}
+
+impl DynamicCors {
+    pub fn new(origins: CorsOrigins, any_when_empty: bool) -> Self {
+        DynamicCors {
+            origins,
+            any_when_empty,
+        }
+    }
+}
 
 impl<S> Layer<S> for DynamicCors {
     type Service = CorsMiddleware<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        CorsMiddleware { inner }
+        CorsMiddleware {
+            inner,
+            origins: self.origins.clone(),
+            any_when_empty: self.any_when_empty,
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct CorsMiddleware<S> {
     inner: S,
+    origins: CorsOrigins,
+    any_when_empty: bool,
+}
+
+fn is_allowed(origins: &CorsOrigins, any_when_empty: bool, origin: &HeaderValue) -> bool {
+    let origins = origins
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    (any_when_empty && origins.is_empty())
+        || origins
+            .iter()
+            .any(|allowed| allowed.as_bytes() == origin.as_bytes())
 }
 
 impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for CorsMiddleware<S>
@@ -37,22 +78,50 @@ where
         self.inner.poll_ready(cx)
     }
 
+    /// Every response varies on `Origin`, including those to requests
+    /// without one, so a cache in front never serves a same-origin or curl
+    /// response to a cross-origin browser or the other way round.
+    ///
+    /// Allowed origins are echoed back rather than answered with `*`, and
+    /// always with `Access-Control-Allow-Credentials: true`: sessions are
+    /// cookies, browsers reject `*` on credentialed requests, and a
+    /// non-credentialed request is served the same way without harm since
+    /// the browser, not the server, decides whether to send cookies.
+    /// Successful preflights from allowed origins also list the methods and
+    /// headers callers may use. Disallowed origins get no CORS headers and
+    /// the browser blocks the response.
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
-        let origin = req.headers().get("origin").cloned();
+        let origin = req
+            .headers()
+            .get(ORIGIN)
+            .filter(|origin| is_allowed(&self.origins, self.any_when_empty, origin))
+            .cloned();
+        let preflight = req.method() == Method::OPTIONS;
         let mut inner = self.inner.clone();
 
         Box::pin(async move {
             let mut res = inner.call(req).await?;
 
+            let answered = res.status().is_success();
+            let headers = res.headers_mut();
             if let Some(origin) = origin {
-                let headers = res.headers_mut();
-                headers.insert("access-control-allow-origin", origin);
+                headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
                 headers.insert(
-                    "access-control-allow-credentials",
+                    ACCESS_CONTROL_ALLOW_CREDENTIALS,
                     HeaderValue::from_static("true"),
                 );
-                headers.insert("vary", HeaderValue::from_static("Origin"));
+                if preflight && answered {
+                    headers.insert(
+                        ACCESS_CONTROL_ALLOW_METHODS,
+                        HeaderValue::from_static("GET, POST, OPTIONS"),
+                    );
+                    headers.insert(
+                        ACCESS_CONTROL_ALLOW_HEADERS,
+                        HeaderValue::from_static("Content-Type, Authorization"),
+                    );
+                }
             }
+            add_vary(headers, "Origin");
 
             Ok(res)
         })
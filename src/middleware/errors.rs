@@ -0,0 +1,101 @@
+use super::{add_vary, buffer_json, is_json};
+use crate::types::JsonMessage;
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{
+        header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderValue,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+/// Error bodies longer than this are replaced by the status reason instead of
+/// being copied into the message.
+const MAX_MESSAGE_LEN: usize = 1024;
+
+/// Rewrites non-JSON error responses (extractor rejections, 405s, body-limit
+/// and other layer rejections) into the same `JsonMessage` shape the handlers
+/// return, keeping the status code and any other headers.
+pub async fn json_errors(res: Response) -> Response {
+    let status = res.status();
+    if !(status.is_client_error() || status.is_server_error()) || is_json(res.headers()) {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let text = to_bytes(body, MAX_MESSAGE_LEN)
+        .await
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty() && !text.starts_with('<'));
+    let message = text.unwrap_or_else(|| {
+        status
+            .canonical_reason()
+            .unwrap_or("Request failed")
+            .to_string()
+    });
+
+    let json = Json(JsonMessage { message }).into_response();
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_TYPE, json.headers()[CONTENT_TYPE].clone());
+    Response::from_parts(parts, Body::new(json.into_body()))
+}
+
+/// `{"message": ...}` bodies larger than this are never inspected.
+const MAX_NEGOTIATED_LEN: u64 = 64 * 1024;
+
+/// Renders `JsonMessage` bodies, errors and plain successes alike, as a single
+/// text line when the client asks for `text/plain` and not JSON. Replies that
+/// add only scalar fields to the message, like `/set`'s `created`, count too;
+/// anything carrying a list or object stays JSON. Handy when poking the API
+/// with curl; `*/*` and JSON clients are unaffected.
+pub async fn negotiate_messages(req: Request, next: Next) -> Response {
+    let wants_text = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/plain") && !accept.contains("json"));
+    let mut res = next.run(req).await;
+    // Any JSON reply could have come back as text, so caches must key on
+    // `Accept` even when this one didn't.
+    if is_json(res.headers()) {
+        add_vary(res.headers_mut(), "Accept");
+    }
+    if !wants_text {
+        return res;
+    }
+
+    let (mut parts, bytes) = match buffer_json(res, MAX_NEGOTIATED_LEN).await {
+        Ok(buffered) => buffered,
+        Err(res) => return res,
+    };
+    let message = serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&bytes)
+        .ok()
+        .filter(|object| {
+            object
+                .values()
+                .all(|value| !value.is_array() && !value.is_object())
+        })
+        .and_then(|mut object| match object.remove("message") {
+            Some(serde_json::Value::String(message)) => Some(message),
+            _ => None,
+        });
+
+    match message {
+        Some(message) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            );
+            Response::from_parts(parts, Body::from(format!("{}\n", message)))
+        }
+        None => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
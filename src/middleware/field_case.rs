@@ -0,0 +1,89 @@
+use super::buffer_json;
+use crate::config::FieldCase;
+use axum::{body::Body, http::header::CONTENT_LENGTH, response::Response};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Bodies above this are passed through unchanged, like `negotiate_messages`
+/// does, rather than buffered for rewriting.
+const MAX_REWRITTEN_LEN: u64 = 8 * 1024 * 1024;
+
+/// Rewrites the keys of JSON responses from `snake_case` to `camelCase` for
+/// `API_FIELD_CASE=camel`, so the structs keep one spelling and every
+/// endpoint switches at once. Only keys that look like field names (lowercase
+/// ASCII, digits and underscores) change, which leaves data used as keys,
+/// such as the user IDs of `/list`, untouched. Streams have no fixed length
+/// and are not rewritten here; the ones carrying JSON (`/events`, JSON Lines)
+/// serialize through [`to_json`] instead.
+pub async fn camel_case_fields(res: Response) -> Response {
+    let (mut parts, bytes) = match buffer_json(res, MAX_REWRITTEN_LEN).await {
+        Ok(buffered) => buffered,
+        Err(res) => return res,
+    };
+    let Ok(mut value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    camel_case_keys(&mut value);
+    match serde_json::to_vec(&value) {
+        Ok(rewritten) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(rewritten))
+        }
+        Err(_) => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
+
+/// `value` as JSON with its keys in `case`, for streamed bodies that
+/// `camel_case_fields` never sees.
+pub fn to_json<T: Serialize>(value: &T, case: FieldCase) -> serde_json::Result<String> {
+    match case {
+        FieldCase::Snake => serde_json::to_string(value),
+        FieldCase::Camel => {
+            let mut value = serde_json::to_value(value)?;
+            camel_case_keys(&mut value);
+            serde_json::to_string(&value)
+        }
+    }
+}
+
+fn camel_case_keys(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            let fields = std::mem::take(object);
+            *object = fields
+                .into_iter()
+                .map(|(key, mut value)| {
+                    camel_case_keys(&mut value);
+                    (camel_case(key), value)
+                })
+                .collect::<Map<_, _>>();
+        }
+        Value::Array(items) => items.iter_mut().for_each(camel_case_keys),
+        _ => {}
+    }
+}
+
+/// `utc_offset_seconds` becomes `utcOffsetSeconds`; anything that isn't a
+/// `snake_case` identifier is returned as is.
+fn camel_case(key: String) -> String {
+    let is_field = key.starts_with(|c: char| c.is_ascii_lowercase())
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+    if !is_field || !key.contains('_') {
+        return key;
+    }
+    let mut out = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
@@ -0,0 +1,25 @@
+use crate::metrics::REQUESTS_IN_FLIGHT;
+use axum::{extract::Request, middleware::Next, response::Response};
+
+/// Decrements on drop, so requests whose connection goes away mid-handler
+/// are still counted out.
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        REQUESTS_IN_FLIGHT.inc();
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        REQUESTS_IN_FLIGHT.dec();
+    }
+}
+
+/// Counts requests until their response is produced.
+pub async fn track_in_flight(req: Request, next: Next) -> Response {
+    let _guard = InFlight::start();
+    next.run(req).await
+}
@@ -0,0 +1,38 @@
+use crate::error::AppError;
+use axum::{
+    extract::{Request, State},
+    http::header::RETRY_AFTER,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Monitoring and clock checks keep working while the server is saturated.
+/// Event streams stay open indefinitely, so they would hold a slot forever.
+const EXEMPT_PATHS: &[&str] = &["/health", "/metrics", "/now", "/events"];
+
+/// Seconds clients are told to wait after being shed.
+const RETRY_AFTER_SECS: &str = "1";
+
+/// Caps concurrent requests at the semaphore's size. Requests over the cap
+/// are answered with 503 straight away instead of queueing, so overload shows
+/// up as fast failures rather than everything timing out.
+pub async fn limit_concurrency(
+    State(permits): State<Arc<Semaphore>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if EXEMPT_PATHS.contains(&req.uri().path()) {
+        return next.run(req).await;
+    }
+
+    let Ok(_permit) = permits.try_acquire_owned() else {
+        return (
+            [(RETRY_AFTER, RETRY_AFTER_SECS)],
+            AppError::Unavailable("Server is busy, try again shortly".into()),
+        )
+            .into_response();
+    };
+    next.run(req).await
+}
@@ -1 +1,66 @@
+pub mod api_key;
+pub mod cache;
+pub mod compression;
 pub mod cors;
+pub mod errors;
+pub mod field_case;
+pub mod inflight;
+pub mod limit;
+pub mod msgpack;
+pub mod path;
+
+use axum::{
+    body::{to_bytes, Body, Bytes, HttpBody},
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE, VARY},
+        response::Parts,
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::Response,
+};
+
+pub fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+/// Buffers a JSON body of known length up to `max` bytes so a layer can
+/// rewrite it. Anything else (other types, streams, larger bodies) comes back
+/// as `Err` untouched. A body that fails while being read becomes an empty
+/// 500, since what was already sent on can't be recovered.
+pub async fn buffer_json(res: Response, max: u64) -> Result<(Parts, Bytes), Response> {
+    let bounded = res.body().size_hint().exact().is_some_and(|len| len <= max);
+    if !is_json(res.headers()) || !bounded {
+        return Err(res);
+    }
+
+    let (mut parts, body) = res.into_parts();
+    match to_bytes(body, max as usize).await {
+        Ok(bytes) => Ok((parts, bytes)),
+        Err(_) => {
+            parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.remove(CONTENT_TYPE);
+            Err(Response::from_parts(parts, Body::empty()))
+        }
+    }
+}
+
+/// Adds `name` to `Vary` unless it, or `*`, is already listed. Other entries,
+/// such as the compression layer's `Accept-Encoding`, are kept.
+pub fn add_vary(headers: &mut HeaderMap, name: &'static str) {
+    let listed = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|listed| {
+            let listed = listed.trim();
+            listed == "*" || listed.eq_ignore_ascii_case(name)
+        });
+    if !listed {
+        headers.append(VARY, HeaderValue::from_static(name));
+    }
+}
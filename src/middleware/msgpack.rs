@@ -0,0 +1,201 @@
+use super::{add_vary, buffer_json, is_json};
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{
+        header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderMap, HeaderValue,
+    },
+    middleware::Next,
+    response::Response,
+};
+use serde_json::Value;
+
+const MSGPACK: &str = "application/msgpack";
+
+/// Bodies above this stay JSON rather than being buffered for transcoding.
+const MAX_TRANSCODED_LEN: u64 = 8 * 1024 * 1024;
+
+/// Sends JSON responses as MessagePack when the client asks for
+/// `application/msgpack`, for bots that resolve users in bulk and would
+/// rather not parse JSON. The handlers still serialize their usual structs;
+/// the finished body is transcoded here, so both formats always carry the
+/// same fields. Streams and anything that isn't JSON pass through.
+pub async fn negotiate_msgpack(req: Request, next: Next) -> Response {
+    let wants_msgpack = wants_msgpack(req.headers());
+    let mut res = next.run(req).await;
+    // Caches must keep the two formats apart even when this reply stays JSON.
+    if is_json(res.headers()) {
+        add_vary(res.headers_mut(), "Accept");
+    }
+    if !wants_msgpack {
+        return res;
+    }
+
+    let (mut parts, bytes) = match buffer_json(res, MAX_TRANSCODED_LEN).await {
+        Ok(buffered) => buffered,
+        Err(res) => return res,
+    };
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    let mut out = Vec::with_capacity(bytes.len());
+    encode(&mut out, &value);
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static(MSGPACK));
+    Response::from_parts(parts, Body::from(out))
+}
+
+/// Whether the client asked for MessagePack. Handlers that send an `ETag`
+/// use this to give each format its own.
+pub fn wants_msgpack(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains(MSGPACK))
+}
+
+/// Writes `value` in the MessagePack format, always using the smallest
+/// encoding the spec allows for each integer, string, array and map.
+fn encode(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(false) => out.push(0xc2),
+        Value::Bool(true) => out.push(0xc3),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                encode_uint(out, n);
+            } else if let Some(n) = n.as_i64() {
+                encode_int(out, n);
+            } else {
+                out.push(0xcb);
+                out.extend_from_slice(&n.as_f64().unwrap_or_default().to_be_bytes());
+            }
+        }
+        Value::String(s) => {
+            encode_len(out, s.len(), 0xa0, 32, [0xd9, 0xda, 0xdb]);
+            out.extend_from_slice(s.as_bytes());
+        }
+        Value::Array(items) => {
+            // There's no 8-bit array length; 0x00 marks it as unused.
+            encode_len(out, items.len(), 0x90, 16, [0x00, 0xdc, 0xdd]);
+            items.iter().for_each(|item| encode(out, item));
+        }
+        Value::Object(object) => {
+            encode_len(out, object.len(), 0x80, 16, [0x00, 0xde, 0xdf]);
+            for (key, value) in object {
+                encode_len(out, key.len(), 0xa0, 32, [0xd9, 0xda, 0xdb]);
+                out.extend_from_slice(key.as_bytes());
+                encode(out, value);
+            }
+        }
+    }
+}
+
+fn encode_uint(out: &mut Vec<u8>, n: u64) {
+    if n < 0x80 {
+        out.push(n as u8);
+    } else if let Ok(n) = u8::try_from(n) {
+        out.extend_from_slice(&[0xcc, n]);
+    } else if let Ok(n) = u16::try_from(n) {
+        out.push(0xcd);
+        out.extend_from_slice(&n.to_be_bytes());
+    } else if let Ok(n) = u32::try_from(n) {
+        out.push(0xce);
+        out.extend_from_slice(&n.to_be_bytes());
+    } else {
+        out.push(0xcf);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+/// Only called for negative numbers; `as_u64` takes the rest.
+fn encode_int(out: &mut Vec<u8>, n: i64) {
+    if n >= -32 {
+        out.push(n as u8);
+    } else if let Ok(n) = i8::try_from(n) {
+        out.extend_from_slice(&[0xd0, n as u8]);
+    } else if let Ok(n) = i16::try_from(n) {
+        out.push(0xd1);
+        out.extend_from_slice(&n.to_be_bytes());
+    } else if let Ok(n) = i32::try_from(n) {
+        out.push(0xd2);
+        out.extend_from_slice(&n.to_be_bytes());
+    } else {
+        out.push(0xd3);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+/// A length header: the `fix` form with `len` in its low bits below
+/// `fix_limit`, else the 8-, 16- or 32-bit form from `sized` (a zero marker
+/// means the type has no 8-bit form).
+fn encode_len(out: &mut Vec<u8>, len: usize, fix: u8, fix_limit: usize, sized: [u8; 3]) {
+    if len < fix_limit {
+        out.push(fix | len as u8);
+    } else if let (true, Ok(len)) = (sized[0] != 0, u8::try_from(len)) {
+        out.extend_from_slice(&[sized[0], len]);
+    } else if let Ok(len) = u16::try_from(len) {
+        out.push(sized[1]);
+        out.extend_from_slice(&len.to_be_bytes());
+    } else {
+        out.push(sized[2]);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn encoded(value: Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode(&mut out, &value);
+        out
+    }
+
+    #[test]
+    fn strings_switch_from_fixstr_to_str8_at_32_bytes() {
+        let fixstr = encoded(json!("a".repeat(31)));
+        assert_eq!(fixstr[0], 0xa0 | 31);
+        assert_eq!(fixstr.len(), 1 + 31);
+
+        let str8 = encoded(json!("a".repeat(32)));
+        assert_eq!(str8[..2], [0xd9, 32]);
+        assert_eq!(str8.len(), 2 + 32);
+    }
+
+    #[test]
+    fn maps_switch_from_fixmap_to_map16_at_16_entries() {
+        let map = |len: usize| {
+            let entries = (0..len).map(|i| (format!("k{:02}", i), json!(null)));
+            Value::Object(entries.collect())
+        };
+        assert_eq!(encoded(map(15))[0], 0x80 | 15);
+        assert_eq!(encoded(map(16))[..3], [0xde, 0x00, 16]);
+    }
+
+    #[test]
+    fn arrays_have_no_8_bit_form() {
+        assert_eq!(encoded(json!(vec![0; 15]))[0], 0x90 | 15);
+        assert_eq!(encoded(json!(vec![0; 16]))[..3], [0xdc, 0x00, 16]);
+    }
+
+    #[test]
+    fn negative_ints_switch_from_fixint_to_int8_below_minus_32() {
+        assert_eq!(encoded(json!(-32)), [0xe0]);
+        assert_eq!(encoded(json!(-33)), [0xd0, (-33i8) as u8]);
+        assert_eq!(encoded(json!(-129)), [0xd1, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn positive_ints_use_the_smallest_form() {
+        assert_eq!(encoded(json!(127)), [0x7f]);
+        assert_eq!(encoded(json!(128)), [0xcc, 0x80]);
+        assert_eq!(encoded(json!(256)), [0xcd, 0x01, 0x00]);
+    }
+}
@@ -0,0 +1,40 @@
+use crate::config::Config;
+use axum::{
+    extract::{Request, State},
+    http::Uri,
+};
+use std::sync::Arc;
+
+/// Static assets are left alone: ServeDir redirects directory paths *to* a
+/// trailing slash, and trimming it again would loop.
+const EXEMPT_PREFIX: &str = "/public/";
+
+/// Strips trailing slashes before routing so `/get/` resolves like `/get`.
+/// Has to wrap the whole router, since route matching has already happened by
+/// the time a `Router::layer` runs.
+pub async fn trim_trailing_slash(State(config): State<Arc<Config>>, mut req: Request) -> Request {
+    let path = req.uri().path();
+    if !config.trim_trailing_slash
+        || path == "/"
+        || !path.ends_with('/')
+        || path.starts_with(EXEMPT_PREFIX)
+    {
+        return req;
+    }
+
+    let trimmed = path.trim_end_matches('/');
+    let trimmed = if trimmed.is_empty() { "/" } else { trimmed };
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}?{}", trimmed, query),
+        None => trimmed.to_string(),
+    };
+
+    let mut parts = req.uri().clone().into_parts();
+    if let Ok(path_and_query) = path_and_query.parse() {
+        parts.path_and_query = Some(path_and_query);
+        if let Ok(uri) = Uri::from_parts(parts) {
+            *req.uri_mut() = uri;
+        }
+    }
+    req
+}
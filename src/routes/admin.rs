@@ -0,0 +1,273 @@
+use crate::config::validate_origin;
+use crate::db::{
+    redis_helper::cache_del,
+    store::{MergeConflict, MergeOutcome},
+    AppState,
+};
+use crate::error::{AppError, AppResult};
+use crate::middleware::api_key::{generate_key, hash_key, require_scope, ApiKey, ApiScope};
+use crate::routes::{
+    auth::end_sessions, info::info_cache_key, timezone::validate_timezone, user_id,
+};
+use crate::types::JsonMessage;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct CreateKeyBody {
+    name: String,
+    scope: ApiScope,
+}
+
+#[derive(Serialize)]
+pub struct CreatedKey {
+    id: String,
+    name: String,
+    scope: ApiScope,
+    key: String,
+}
+
+#[derive(Serialize)]
+pub struct KeyInfo {
+    id: String,
+    name: String,
+    scope: String,
+    created_at: String,
+    revoked: bool,
+}
+
+pub async fn list_keys(
+    State(state): State<AppState>,
+    api_key: Option<Extension<ApiKey>>,
+) -> AppResult<Json<Vec<KeyInfo>>> {
+    require_scope(api_key.as_deref(), ApiScope::Admin)?;
+
+    let rows = state
+        .keys
+        .list_keys()
+        .await
+        .map_err(AppError::db("Failed to fetch keys"))?;
+
+    let keys = rows
+        .into_iter()
+        .map(|r| KeyInfo {
+            id: r.id,
+            name: r.name,
+            scope: r.scope,
+            created_at: r.created_at,
+            revoked: r.revoked,
+        })
+        .collect();
+    Ok(Json(keys))
+}
+
+pub async fn create_key(
+    State(state): State<AppState>,
+    api_key: Option<Extension<ApiKey>>,
+    Json(body): Json<CreateKeyBody>,
+) -> AppResult<impl IntoResponse> {
+    require_scope(api_key.as_deref(), ApiScope::Admin)?;
+
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(AppError::bad_request("Key name is required"));
+    }
+
+    let id = Uuid::now_v7().to_string();
+    let key = generate_key();
+
+    state
+        .keys
+        .insert_key(&id, name, &hash_key(&key), body.scope.as_str())
+        .await
+        .map_err(AppError::db("Failed to create key"))?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedKey {
+            id,
+            name: name.to_string(),
+            scope: body.scope,
+            key,
+        }),
+    ))
+}
+
+pub async fn revoke_key(
+    State(state): State<AppState>,
+    api_key: Option<Extension<ApiKey>>,
+    Path(id): Path<String>,
+) -> AppResult<Json<JsonMessage>> {
+    require_scope(api_key.as_deref(), ApiScope::Admin)?;
+
+    let revoked = state
+        .keys
+        .revoke_key(&id)
+        .await
+        .map_err(AppError::db("Failed to revoke key"))?;
+
+    if !revoked {
+        return Err(AppError::not_found("Key not found"));
+    }
+    Ok(Json(JsonMessage {
+        message: "Key revoked".into(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct MergeBody {
+    #[serde(deserialize_with = "user_id")]
+    from_user_id: String,
+    #[serde(deserialize_with = "user_id")]
+    to_user_id: String,
+    #[serde(default)]
+    on_conflict: MergeConflict,
+}
+
+/// Moves a user's timezone row to a new user ID. The old ID's sessions are
+/// ended first: each one carries its user ID, and its next `/set` would
+/// otherwise re-create the row being moved. The user logs in again as the new
+/// ID.
+pub async fn merge_users(
+    State(state): State<AppState>,
+    api_key: Option<Extension<ApiKey>>,
+    Json(body): Json<MergeBody>,
+) -> AppResult<Json<JsonMessage>> {
+    require_scope(api_key.as_deref(), ApiScope::Admin)?;
+
+    let from = body.from_user_id.trim();
+    let to = body.to_user_id.trim();
+    if from.is_empty() || to.is_empty() || from == to {
+        return Err(AppError::bad_request(
+            "from_user_id and to_user_id must be distinct, non-empty IDs",
+        ));
+    }
+
+    end_sessions(&state, from).await?;
+    let message = merge_rows(&state, from, to, body.on_conflict).await?;
+    cache_del(&state.kv, &info_cache_key(from)).await;
+    cache_del(&state.kv, &info_cache_key(to)).await;
+    Ok(Json(JsonMessage {
+        message: message.into(),
+    }))
+}
+
+async fn merge_rows(
+    state: &AppState,
+    from: &str,
+    to: &str,
+    on_conflict: MergeConflict,
+) -> AppResult<&'static str> {
+    let outcome = state
+        .store
+        .merge(from, to, on_conflict)
+        .await
+        .map_err(AppError::db("Merge failed"))?;
+
+    match outcome {
+        MergeOutcome::SourceMissing => Err(AppError::not_found("Source user not found")),
+        MergeOutcome::TargetExists => Err(AppError::conflict("Target user already exists")),
+        MergeOutcome::KeptTarget => Ok("Kept target entry and removed source"),
+        MergeOutcome::ReplacedTarget => Ok("Replaced target entry with source"),
+        MergeOutcome::Merged => Ok("User merged"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RemapBody {
+    from: String,
+    to: String,
+}
+
+#[derive(Serialize)]
+pub struct RemapResponse {
+    from: String,
+    to: String,
+    updated: usize,
+}
+
+/// Moves every entry stored under one zone name to another, e.g. after an
+/// IANA rename. `from` is matched exactly and needn't parse any more; `to` has
+/// to pass the same checks as `/set` and is stored under its canonical
+/// spelling.
+pub async fn remap_timezones(
+    State(state): State<AppState>,
+    api_key: Option<Extension<ApiKey>>,
+    Json(body): Json<RemapBody>,
+) -> AppResult<Json<RemapResponse>> {
+    require_scope(api_key.as_deref(), ApiScope::Admin)?;
+
+    let from = body.from.trim();
+    let to = validate_timezone(&body.to, &state.config)
+        .map_err(|message| AppError::bad_request(format!("to: {}", message)))?
+        .name();
+    if from.is_empty() || from == to {
+        return Err(AppError::bad_request(
+            "from must be a non-empty name different from to",
+        ));
+    }
+
+    let user_ids = state
+        .store
+        .remap(from, to)
+        .await
+        .map_err(AppError::db("Remap failed"))?;
+    for id in &user_ids {
+        cache_del(&state.kv, &info_cache_key(id)).await;
+    }
+    Ok(Json(RemapResponse {
+        from: from.to_string(),
+        to: to.to_string(),
+        updated: user_ids.len(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct CorsReloadBody {
+    origins: Vec<String>,
+}
+
+/// Replaces the CORS allowlist without a restart. An empty list goes back to
+/// reflecting any origin, or to none with `CORS_ALLOW_ANY_ORIGIN` off. The
+/// change is not persisted; `CORS_ALLOWED_ORIGINS` applies again on the next
+/// start.
+pub async fn reload_cors(
+    State(state): State<AppState>,
+    api_key: Option<Extension<ApiKey>>,
+    Json(body): Json<CorsReloadBody>,
+) -> AppResult<Json<JsonMessage>> {
+    require_scope(api_key.as_deref(), ApiScope::Admin)?;
+
+    let origins: Vec<String> = body
+        .origins
+        .iter()
+        .map(|origin| origin.trim().to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    if let Some(reason) = origins.iter().find_map(|o| validate_origin(o).err()) {
+        return Err(AppError::BadRequest(reason));
+    }
+
+    let reflect_any = origins.is_empty();
+    *state
+        .cors_origins
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = origins;
+
+    let message = if reflect_any && !state.config.cors_any_origin {
+        "CORS now allows no origins"
+    } else if reflect_any {
+        "CORS now reflects any origin"
+    } else {
+        "CORS allowlist updated"
+    };
+    Ok(Json(JsonMessage {
+        message: message.into(),
+    }))
+}
@@ -0,0 +1,91 @@
+use crate::db::{
+    redis_helper::{cache_get, cache_set, ttl_with_jitter},
+    store::retry_read,
+    AppState,
+};
+use crate::error::{AppError, AppResult};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Seconds a result is reused. Half- and quarter-hour zones cross into the
+/// hour at :30 and :45, so the answer can go stale by at most this much.
+const CACHE_TTL: u64 = 60;
+
+#[derive(Deserialize)]
+pub struct AtHourQuery {
+    hour: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserAtHour {
+    id: String,
+    username: String,
+    timezone: String,
+    local_time: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AtHourResponse {
+    hour: u32,
+    count: usize,
+    users: Vec<UserAtHour>,
+}
+
+/// Every stored user whose local clock currently reads `hour`, for "good
+/// morning" style automation. Reads the whole table, computing each distinct
+/// zone once, so results are cached for `CACHE_TTL` seconds.
+pub async fn at_hour(
+    State(state): State<AppState>,
+    Query(query): Query<AtHourQuery>,
+) -> AppResult<impl IntoResponse> {
+    let hour = match u32::try_from(query.hour) {
+        Ok(hour) if hour < 24 => hour,
+        _ => return Err(AppError::bad_request("hour must be between 0 and 23")),
+    };
+
+    let key = format!("at-hour:{}", hour);
+    if let Some(cached) = cache_get::<AtHourResponse>(&state.kv, &key).await {
+        return Ok(Json(cached));
+    }
+
+    let records = retry_read(|| state.store.list()).await?;
+
+    let now = Utc::now();
+    let mut local_times: HashMap<String, Option<String>> = HashMap::new();
+    let mut users = Vec::new();
+    for record in records {
+        let timezone = record.timezone;
+        let local_time = local_times
+            .entry(timezone.clone())
+            .or_insert_with(|| {
+                let tz = timezone.parse::<Tz>().ok()?;
+                let local = now.with_timezone(&tz);
+                (local.hour() == hour).then(|| local.format("%H:%M").to_string())
+            })
+            .clone();
+        if let Some(local_time) = local_time {
+            users.push(UserAtHour {
+                id: record.user_id,
+                username: record.username,
+                timezone,
+                local_time,
+            });
+        }
+    }
+
+    let response = AtHourResponse {
+        hour,
+        count: users.len(),
+        users,
+    };
+    let ttl = ttl_with_jitter(CACHE_TTL, state.config.ttl_jitter_pct);
+    cache_set(&state.kv, &key, &response, ttl).await;
+    Ok(Json(response))
+}
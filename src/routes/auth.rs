@@ -1,26 +1,43 @@
-use crate::db::AppState;
-
-use crate::types::JsonMessage;
+use crate::clock::unix_now;
+use crate::config::{Config, SessionIdFormat};
+use crate::db::{
+    redis_helper::{cache_del, ttl_with_jitter},
+    store::retry_read,
+    AppState,
+};
+use crate::discord_tokens::{self, DiscordTokens};
+use crate::error::{AppError, AppResult};
+use crate::metrics;
+use crate::routes::info::info_cache_key;
 use axum::{
     extract::{Query, State},
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
+    http::{
+        header::{COOKIE, HOST},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
     Json,
 };
 use headers::{Cookie, HeaderMapExt};
-use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
-use sqlx::Row;
-use std::{collections::HashMap, env};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
 use uuid::Uuid;
 
+const SESSION_TTL: u64 = 3600;
+const OAUTH_STATE_COOKIE: &str = "oauth_state";
+
+/// The most guilds Discord returns from `/users/@me/guilds` in one page.
+const GUILD_PAGE_SIZE: usize = 200;
+
 #[derive(Deserialize)]
 pub struct CallbackQuery {
     code: String,
     state: Option<String>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct DiscordUser {
     pub id: String,
     pub username: String,
@@ -28,239 +45,845 @@ pub struct DiscordUser {
     pub avatar: Option<String>,
 }
 
+impl DiscordUser {
+    /// Reads a `/users/@me` response field by field, so a renamed or dropped
+    /// profile field degrades that field instead of failing the login. Only a
+    /// missing `id` is fatal. `username` falls back to `global_name`, and
+    /// `discriminator` to `"0"`, which Discord uses for migrated accounts.
+    fn from_profile(profile: &serde_json::Value) -> Option<DiscordUser> {
+        let field = |name: &str| profile.get(name).and_then(|v| v.as_str());
+        let Some(id) = field("id").filter(|id| !id.is_empty()) else {
+            warn!("Discord profile has no user ID");
+            return None;
+        };
+
+        let mut absent = Vec::new();
+        let mut text = |name: &'static str| {
+            let value = field(name);
+            if value.is_none() {
+                absent.push(name);
+            }
+            value
+        };
+        let username = text("username").or_else(|| field("global_name"));
+        let discriminator = text("discriminator");
+        // `null` is how Discord reports "no custom avatar"; only a missing key
+        // is worth a warning.
+        let avatar = field("avatar");
+        if profile.get("avatar").is_none() {
+            absent.push("avatar");
+        }
+
+        if !absent.is_empty() {
+            warn!(
+                "Discord profile for {} is missing {}",
+                id,
+                absent.join(", ")
+            );
+        }
+
+        Some(DiscordUser {
+            id: id.to_string(),
+            username: username.unwrap_or_default().to_string(),
+            discriminator: discriminator.unwrap_or("0").to_string(),
+            avatar: avatar.map(String::from),
+        })
+    }
+}
+
+/// Copies a fresh login's username and avatar onto the user's stored entry, if
+/// they have one, so renames show up without another `/set`. Runs detached
+/// from the login and only logs failures.
+async fn refresh_profile(state: AppState, user: DiscordUser) {
+    let updated = state
+        .store
+        .update_profile(&user.id, &user.username, user.avatar.as_deref())
+        .await;
+
+    match updated {
+        Ok(true) => {
+            cache_del(&state.kv, &info_cache_key(&user.id)).await;
+        }
+        Ok(false) => {}
+        Err(err) => warn!("Failed to refresh profile for {}: {}", user.id, err),
+    }
+}
+
+/// Builds the Discord CDN URL for a user's avatar, falling back to the default
+/// avatar Discord assigns when the user hasn't uploaded one.
+pub fn avatar_url(user_id: &str, avatar: Option<&str>) -> String {
+    match avatar {
+        Some(hash) => {
+            let ext = if hash.starts_with("a_") { "gif" } else { "png" };
+            format!(
+                "https://cdn.discordapp.com/avatars/{}/{}.{}",
+                user_id, hash, ext
+            )
+        }
+        None => {
+            let index = user_id.parse::<u64>().map(|id| (id >> 22) % 6).unwrap_or(0);
+            format!("https://cdn.discordapp.com/embed/avatars/{}.png", index)
+        }
+    }
+}
+
+/// What `session:<id>` holds. `created_at` is the Unix time of the login, for
+/// `SESSION_MAX_LIFETIME`; sessions stored before it was recorded lack it and
+/// are bounded by their TTL alone.
+#[derive(Deserialize, Serialize)]
+struct StoredSession {
+    #[serde(flatten)]
+    user: DiscordUser,
+    #[serde(default)]
+    created_at: Option<u64>,
+}
+
 #[derive(Serialize)]
 pub struct AuthResponse {
     user: DiscordUser,
     session: String,
 }
 
+/// The only place the session cookie is built, so the name and attributes
+/// can't drift between login paths.
+fn session_cookie(config: &Config, session_id: &str, ttl: u64) -> HeaderValue {
+    // Browsers drop `SameSite=None` cookies that aren't `Secure`.
+    let same_site = if config.secure_cookies {
+        "SameSite=None; Secure"
+    } else {
+        "SameSite=Lax"
+    };
+    format!(
+        "{}={}; Max-Age={}; Path=/; {}; HttpOnly",
+        config.session_cookie_name, session_id, ttl, same_site
+    )
+    .parse()
+    .unwrap()
+}
+
+const HYPHENATED_UUID_LEN: usize = 36;
+const ULID_LEN: usize = 26;
+const RANDOM128_LEN: usize = 32;
+
+/// Crockford's base32 alphabet, as used by ULIDs.
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+fn new_session_id(format: SessionIdFormat) -> String {
+    match format {
+        SessionIdFormat::Uuidv7 => Uuid::now_v7().to_string(),
+        SessionIdFormat::Ulid => new_ulid(),
+        SessionIdFormat::Random128 => format!("{:032x}", rand::random::<u128>()),
+    }
+}
+
+/// A 48-bit millisecond timestamp followed by 80 random bits, written as 26
+/// base32 characters so IDs sort by creation time.
+fn new_ulid() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let value = ((millis & ((1 << 48) - 1)) << 80) | (rand::random::<u128>() >> 48);
+    (0..ULID_LEN)
+        .rev()
+        .map(|i| CROCKFORD[(value >> (i * 5)) as usize & 31] as char)
+        .collect()
+}
+
+/// The form `handle_callback` issues session IDs in. Only the canonical
+/// spelling counts (hyphenated UUIDs, upper-case ULIDs, lower-case hex), so
+/// one session can't be reached under several keys.
+fn is_session_id(value: &str, format: SessionIdFormat) -> bool {
+    match format {
+        SessionIdFormat::Uuidv7 => {
+            value.len() == HYPHENATED_UUID_LEN
+                && Uuid::try_parse(value)
+                    .is_ok_and(|id| id.get_version_num() == 7 && id.to_string() == value)
+        }
+        // 26 characters hold 130 bits, so the first may only carry three.
+        SessionIdFormat::Ulid => {
+            value.len() == ULID_LEN
+                && value.starts_with(|c: char| ('0'..='7').contains(&c))
+                && value.bytes().all(|b| CROCKFORD.contains(&b))
+        }
+        SessionIdFormat::Random128 => {
+            value.len() == RANDOM128_LEN
+                && value
+                    .bytes()
+                    .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        }
+    }
+}
+
+fn user_sessions_key(user_id: &str) -> String {
+    format!("user_sessions:{}", user_id)
+}
+
+/// Adds a new session to `user_sessions:<id>` and, with
+/// `MAX_SESSIONS_PER_USER` set, ends the user's other sessions beyond it,
+/// soonest to expire first. Members are scored by expiry time, so lapsed ones
+/// are pruned by score. Failures are logged and never fail the login.
+async fn track_session(state: &AppState, user_id: &str, session_id: &str, ttl: u64) {
+    let key = user_sessions_key(user_id);
+    let now = unix_now();
+    // Long enough to outlive any session in the set, whatever its jitter.
+    let longest = SESSION_TTL + SESSION_TTL * u64::from(state.config.ttl_jitter_pct.min(100)) / 100;
+
+    let members = state
+        .kv
+        .track(&key, session_id, now + ttl, now, longest)
+        .await;
+    let others: Vec<String> = match members {
+        Ok(members) => members.into_iter().filter(|id| id != session_id).collect(),
+        Err(err) => {
+            warn!("Failed to track session for {}: {}", user_id, err);
+            return;
+        }
+    };
+
+    let Some(max) = state.config.max_sessions_per_user else {
+        return;
+    };
+    let excess = (others.len() + 1).saturating_sub(max);
+    if excess == 0 {
+        return;
+    }
+    let evicted = &others[..excess];
+    let result = match state.kv.untrack(&key, evicted).await {
+        Ok(()) => state.kv.del(&session_keys(evicted)).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        warn!("Failed to end old sessions for {}: {}", user_id, err);
+    }
+}
+
+/// The `session:` entry of each ID and the Discord tokens kept alongside it.
+fn session_keys(ids: &[String]) -> Vec<String> {
+    let mut keys = Vec::with_capacity(ids.len() * 2);
+    for id in ids {
+        keys.push(format!("session:{}", id));
+        keys.push(discord_tokens::storage_key(id));
+    }
+    keys
+}
+
+/// Ends every session `user_id` holds, e.g. before an admin merge moves their
+/// entry to another ID. Sessions from before they were tracked aren't found.
+pub async fn end_sessions(state: &AppState, user_id: &str) -> AppResult<()> {
+    let key = user_sessions_key(user_id);
+    let mut keys = session_keys(&state.kv.members(&key).await?);
+    keys.push(key);
+    state.kv.del(&keys).await?;
+    Ok(())
+}
+
 pub async fn get_user_from_session(
     headers: &HeaderMap,
     state: &AppState,
-) -> Result<DiscordUser, impl IntoResponse> {
-    let Some(cookie_header) = headers.typed_get::<Cookie>() else {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Missing session cookie".into(),
-            }),
-        ));
+) -> AppResult<DiscordUser> {
+    session_from_headers(headers, state)
+        .await
+        .map(|(_, session)| session.user)
+}
+
+/// The request's session ID and what is stored under it.
+async fn session_from_headers(
+    headers: &HeaderMap,
+    state: &AppState,
+) -> AppResult<(String, StoredSession)> {
+    // The cookie parser silently skips values that aren't valid text, which
+    // would surface as a confusing "missing" cookie. It is still a request
+    // without a usable session, so it gets a 401 with its own message.
+    if headers
+        .get_all(COOKIE)
+        .iter()
+        .any(|value| value.to_str().is_err())
+    {
+        return Err(AppError::unauthorized("Malformed Cookie header"));
+    }
+
+    let cookie_header = headers
+        .typed_get::<Cookie>()
+        .ok_or_else(|| AppError::unauthorized("Missing session cookie"))?;
+
+    let session_id = cookie_header
+        .get(&state.config.session_cookie_name)
+        .ok_or_else(|| AppError::unauthorized("Missing session ID"))?;
+
+    // Cleared cookies are sent back empty until the browser drops them.
+    if session_id.trim().is_empty() {
+        return Err(AppError::unauthorized("Empty session ID"));
+    }
+
+    // Anything not shaped like an ID we issue never reaches Redis as part of
+    // a key.
+    if !is_session_id(session_id, state.config.session_id_format) {
+        return Err(AppError::unauthorized("Invalid session ID"));
+    }
+
+    let key = format!("session:{}", session_id);
+    let json = state
+        .kv
+        .get(&key)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Session not found"))?;
+
+    let session = serde_json::from_str::<StoredSession>(&json)
+        .map_err(|_| AppError::unauthorized("Invalid user session"))?;
+
+    // The TTL is capped at login too, so this only catches sessions whose
+    // expiry was pushed out since, or that were created under a longer limit.
+    if let (Some(max), Some(created_at)) = (state.config.session_max_lifetime, session.created_at) {
+        if unix_now().saturating_sub(created_at) >= max.as_secs() {
+            cache_del(&state.kv, &key).await;
+            cache_del(&state.kv, &discord_tokens::storage_key(session_id)).await;
+            return Err(AppError::unauthorized(
+                "Session expired, please log in again",
+            ));
+        }
+    }
+    Ok((session_id.to_string(), session))
+}
+
+/// Binds the OAuth `state` to the browser that started the login, so a
+/// callback link crafted by someone else is rejected.
+fn oauth_state_cookie(config: &Config, state_id: &str, ttl: u64) -> HeaderValue {
+    format!(
+        "{}={}; Max-Age={}; Path=/auth/discord; SameSite=Lax;{} HttpOnly",
+        OAUTH_STATE_COOKIE,
+        state_id,
+        ttl,
+        if config.secure_cookies {
+            " Secure;"
+        } else {
+            ""
+        }
+    )
+    .parse()
+    .unwrap()
+}
+
+/// Starts a login. A one-time state token is stored in Redis together with
+/// the post-login redirect and expires after `OAUTH_STATE_TTL` seconds if the
+/// login is abandoned.
+pub async fn start_oauth(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> AppResult<Response> {
+    let discord = &state.config.discord;
+    let state_id = Uuid::new_v4().simple().to_string();
+    let redirect = params.get("redirect").cloned().unwrap_or_default();
+    let ttl = ttl_with_jitter(state.config.oauth_state_ttl, state.config.ttl_jitter_pct);
+
+    state
+        .kv
+        .set_ex(&format!("oauth_state:{}", state_id), &redirect, ttl)
+        .await?;
+    metrics::OAUTH_STATES_CREATED.inc();
+
+    // Guild membership is only visible with the extra scope, so it's only
+    // asked for when a guild is required.
+    let scope = if discord.required_guild_id.is_some() {
+        "identify%20guilds"
+    } else {
+        "identify"
     };
+    let url = format!(
+		"https://discord.com/oauth2/authorize?client_id={}&redirect_uri={}&response_type=code&scope={}&state={}",
+		discord.client_id,
+		urlencoding::encode(&redirect_uri(&state.config, &headers)),
+		scope,
+		state_id
+	);
 
-    let Some(session_id) = cookie_header.get("session") else {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Missing session ID".into(),
-            }),
-        ));
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Set-Cookie",
+        oauth_state_cookie(&state.config, &state_id, ttl),
+    );
+    headers.insert(axum::http::header::LOCATION, url.parse().unwrap());
+
+    Ok((StatusCode::FOUND, headers).into_response())
+}
+
+/// The OAuth `redirect_uri` for a request. When the host it came in on
+/// (`X-Forwarded-Host` with `TRUST_PROXY`, else `Host`) is one of
+/// `REDIRECT_HOSTS`, `REDIRECT_URI` is rebuilt on that host so each domain
+/// gets its login back; any other host gets `REDIRECT_URI` unchanged. Only
+/// exact allow-list matches are used, so a forged header can't point Discord
+/// elsewhere.
+fn redirect_uri(config: &Config, headers: &HeaderMap) -> String {
+    let discord = &config.discord;
+    let configured = || discord.redirect_uri.clone();
+    if discord.redirect_hosts.is_empty() {
+        return configured();
+    }
+
+    let forwarded = config
+        .trust_proxy
+        .then(|| headers.get("x-forwarded-host"))
+        .flatten();
+    let Some(host) = forwarded
+        .or_else(|| headers.get(HOST))
+        .and_then(|v| v.to_str().ok())
+        // A proxy chain lists the original host first.
+        .and_then(|v| v.split(',').next())
+        .map(|host| host.trim().to_ascii_lowercase())
+    else {
+        return configured();
     };
+    if !discord.redirect_hosts.contains(&host) {
+        return configured();
+    }
 
-    let mut redis = state.redis.clone();
-    let key = format!("session:{}", session_id);
-    let Ok(json) = redis.get::<_, String>(&key).await else {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Session not found".into(),
-            }),
-        ));
+    let Ok(mut url) = reqwest::Url::parse(&discord.redirect_uri) else {
+        return configured();
     };
+    // Parsed with the same scheme so a default port reads back as none.
+    let Ok(origin) = reqwest::Url::parse(&format!("{}://{}", url.scheme(), host)) else {
+        return configured();
+    };
+    if url.set_host(origin.host_str()).is_err() || url.set_port(origin.port()).is_err() {
+        return configured();
+    }
+    url.to_string()
+}
 
-    let Ok(user) = serde_json::from_str::<DiscordUser>(&json) else {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Invalid user session".into(),
-            }),
-        ));
+/// Longest post-login redirect followed; it is stored with the OAuth state.
+const MAX_REDIRECT_LEN: usize = 2048;
+
+/// Whether a post-login redirect stays on this site or goes to an allowed
+/// host. `//host` and `/\host` are absolute to browsers despite the leading
+/// slash, so they count as absolute here too.
+fn allowed_redirect(target: &str, allowed_hosts: &[String]) -> bool {
+    if target.len() > MAX_REDIRECT_LEN || target.chars().any(|c| c.is_control() || c == '\\') {
+        return false;
+    }
+    if target.starts_with('/') && !target.starts_with("//") {
+        return true;
+    }
+    let Ok(url) = reqwest::Url::parse(target) else {
+        return false;
+    };
+    let Some(host) = url.host_str() else {
+        return false;
     };
+    let with_port = url.port().map(|port| format!("{}:{}", host, port));
+    matches!(url.scheme(), "http" | "https")
+        && allowed_hosts
+            .iter()
+            .any(|allowed| allowed == host || Some(allowed) == with_port.as_ref())
+}
 
-    Ok(user)
+/// Discord codes are short URL-safe tokens; anything else is not worth a
+/// token exchange.
+fn is_plausible_code(code: &str, max_len: usize) -> bool {
+    !code.is_empty()
+        && code.len() <= max_len
+        && code
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~'))
 }
 
-pub async fn start_oauth(Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
-    let client_id = env::var("CLIENT_ID").unwrap_or_default();
-    let redirect_uri = env::var("REDIRECT_URI").unwrap_or_default();
+/// States are issued by `start_oauth` as simple UUIDs.
+fn is_oauth_state(value: &str) -> bool {
+    value.len() == 32
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
 
-    let mut url = format!(
-		"https://discord.com/oauth2/authorize?client_id={}&redirect_uri={}&response_type=code&scope=identify",
-		client_id, redirect_uri
-	);
+/// Redeems the state token created by [`start_oauth`], returning the stored
+/// redirect target. The token is deleted as it is read so it works only once.
+async fn consume_oauth_state(
+    state: &AppState,
+    headers: &HeaderMap,
+    state_id: Option<&str>,
+) -> AppResult<String> {
+    let invalid = || AppError::bad_request("Invalid OAuth state");
 
-    if let Some(redirect) = params.get("redirect") {
-        url.push_str(&format!("&state={}", urlencoding::encode(redirect)));
+    let state_id = state_id
+        .filter(|id| is_oauth_state(id))
+        .ok_or_else(invalid)?;
+    let cookie_state = headers
+        .typed_get::<Cookie>()
+        .and_then(|cookie| cookie.get(OAUTH_STATE_COOKIE).map(String::from));
+    if cookie_state.as_deref() != Some(state_id) {
+        return Err(invalid());
     }
 
-    (StatusCode::FOUND, [(axum::http::header::LOCATION, url)]).into_response()
+    let redirect = state
+        .kv
+        .get_del(&format!("oauth_state:{}", state_id))
+        .await?
+        .ok_or_else(|| AppError::bad_request("OAuth state expired, please log in again"))?;
+    metrics::OAUTH_STATES_CONSUMED.inc();
+    Ok(redirect)
+}
+
+/// Whether the token's user is in `guild_id`, paging through their guilds
+/// since Discord lists at most [`GUILD_PAGE_SIZE`] at a time. A token Discord
+/// won't list guilds for (the user dropped the `guilds` scope) counts as not a
+/// member.
+async fn is_guild_member(state: &AppState, access_token: &str, guild_id: &str) -> AppResult<bool> {
+    let mut after = String::from("0");
+    loop {
+        let res = state
+            .http
+            .get("https://discord.com/api/users/@me/guilds")
+            .query(&[("limit", GUILD_PAGE_SIZE.to_string()), ("after", after)])
+            .header("Authorization", format!("Bearer {}", access_token))
+            .send()
+            .await
+            .map_err(AppError::external("Failed to fetch guilds"))?;
+        if matches!(
+            res.status(),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+        ) {
+            return Ok(false);
+        }
+        let guilds = res
+            .error_for_status()
+            .map_err(AppError::external("Failed to fetch guilds"))?
+            .json::<Vec<serde_json::Value>>()
+            .await
+            .map_err(AppError::external("Failed to parse guilds"))?;
+
+        let ids: Vec<&str> = guilds.iter().filter_map(|g| g["id"].as_str()).collect();
+        if ids.contains(&guild_id) {
+            return Ok(true);
+        }
+        match ids.last() {
+            Some(last) if guilds.len() >= GUILD_PAGE_SIZE => after = last.to_string(),
+            _ => return Ok(false),
+        }
+    }
+}
+
+/// The `/users/@me` profile behind `access_token`.
+async fn fetch_user(state: &AppState, access_token: &str) -> AppResult<DiscordUser> {
+    let profile = state
+        .http
+        .get("https://discord.com/api/users/@me")
+        .header("Authorization", format!("Bearer {}", access_token))
+        .send()
+        .await
+        .map_err(AppError::external("Failed to fetch user"))?
+        .json::<serde_json::Value>()
+        .await
+        .map_err(AppError::external("Failed to parse user"))?;
+    DiscordUser::from_profile(&profile).ok_or_else(|| AppError::External {
+        message: "Failed to parse user",
+        detail: "profile has no user ID".into(),
+    })
 }
 
 pub async fn handle_callback(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Query(query): Query<CallbackQuery>,
-) -> impl IntoResponse {
-    let client_id = env::var("CLIENT_ID").unwrap();
-    let client_secret = env::var("CLIENT_SECRET").unwrap();
-    let redirect_uri = env::var("REDIRECT_URI").unwrap();
+) -> AppResult<Response> {
+    // Checked before the state is consumed, so a mangled link can be retried
+    // from the same login.
+    if !is_plausible_code(&query.code, state.config.oauth_code_max_len) {
+        return Err(AppError::bad_request("Invalid OAuth code"));
+    }
+
+    let redirect = consume_oauth_state(&state, &headers, query.state.as_deref()).await?;
+
+    let discord = &state.config.discord;
+    // Discord only accepts the exchange with the redirect_uri the login was
+    // started with, which came in on this same host.
+    let redirect_uri = redirect_uri(&state.config, &headers);
 
     let form = [
-        ("client_id", client_id.as_str()),
-        ("client_secret", client_secret.as_str()),
+        ("client_id", discord.client_id.as_str()),
+        ("client_secret", discord.client_secret.as_str()),
         ("grant_type", "authorization_code"),
         ("code", &query.code),
         ("redirect_uri", redirect_uri.as_str()),
     ];
 
-    let token_res = reqwest::Client::new()
+    let token_json = state
+        .http
         .post("https://discord.com/api/oauth2/token")
         .form(&form)
         .send()
-        .await;
+        .await
+        .map_err(AppError::external("Failed to exchange token"))?
+        .json::<serde_json::Value>()
+        .await
+        .map_err(AppError::external("Invalid token response"))?;
 
-    let Ok(res) = token_res else {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(JsonMessage {
-                message: "Failed to exchange token".into(),
-            }),
-        )
-            .into_response();
-    };
+    let tokens = DiscordTokens::from_response(&token_json)
+        .ok_or_else(|| AppError::unauthorized("Access token not found"))?;
 
-    let Ok(token_json) = res.json::<serde_json::Value>().await else {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(JsonMessage {
-                message: "Invalid token response".into(),
-            }),
-        )
-            .into_response();
-    };
-
-    let Some(access_token) = token_json["access_token"].as_str() else {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Access token not found".into(),
-            }),
-        )
-            .into_response();
-    };
+    let user = fetch_user(&state, &tokens.access_token).await?;
 
-    let user_res = reqwest::Client::new()
-        .get("https://discord.com/api/users/@me")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .send()
-        .await;
+    if let Some(guild_id) = &discord.required_guild_id {
+        if !is_guild_member(&state, &tokens.access_token, guild_id).await? {
+            return Err(AppError::forbidden(
+                "Only members of this Discord server can log in",
+            ));
+        }
+    }
 
-    let Ok(user_res) = user_res else {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(JsonMessage {
-                message: "Failed to fetch user".into(),
-            }),
-        )
-            .into_response();
+    let session_id = new_session_id(state.config.session_id_format);
+    let mut session_ttl = ttl_with_jitter(SESSION_TTL, state.config.ttl_jitter_pct);
+    if let Some(max) = state.config.session_max_lifetime {
+        session_ttl = session_ttl.min(max.as_secs());
+    }
+    let session = StoredSession {
+        user: user.clone(),
+        created_at: Some(unix_now()),
     };
-
-    let Ok(user) = user_res.json::<DiscordUser>().await else {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(JsonMessage {
-                message: "Failed to parse user".into(),
-            }),
+    state
+        .kv
+        .set_ex(
+            &format!("session:{}", session_id),
+            &serde_json::to_string(&session).unwrap(),
+            session_ttl,
         )
-            .into_response();
-    };
+        .await?;
 
-    let session_id = Uuid::now_v7().to_string();
-    let mut redis = state.redis.clone();
-    let _ = redis
-        .set_ex::<_, _, ()>(
-            format!("session:{}", session_id),
-            serde_json::to_string(&user).unwrap(),
-            3600,
-        )
-        .await;
+    discord_tokens::store(&state, &session_id, &tokens, session_ttl).await;
+    track_session(&state, &user.id, &session_id, session_ttl).await;
+    tokio::spawn(refresh_profile(state.clone(), user.clone()));
 
-    if let Some(redirect_url) = &query.state {
-        let redirect_target = urlencoding::decode(redirect_url)
-            .map(|s| s.into_owned())
-            .unwrap_or("/".to_string());
+    let redirect = match redirect.as_str() {
+        "" => redirect,
+        target if allowed_redirect(target, &state.config.oauth_allowed_redirects) => redirect,
+        target => {
+            warn!("Ignoring disallowed post-login redirect {:?}", target);
+            "/".to_string()
+        }
+    };
 
+    if !redirect.is_empty() {
         let mut headers = HeaderMap::new();
         headers.insert(
             "Set-Cookie",
-            format!(
-                "session={}; Max-Age=3600; Path=/; SameSite=None; Secure; HttpOnly",
-                session_id
-            )
-            .parse()
-            .unwrap(),
+            session_cookie(&state.config, &session_id, session_ttl),
         );
+        headers.append("Set-Cookie", oauth_state_cookie(&state.config, "", 0));
         headers.insert(
             axum::http::header::LOCATION,
-            redirect_target.parse().unwrap(),
+            redirect
+                .parse()
+                .unwrap_or_else(|_| HeaderValue::from_static("/")),
         );
 
-        (StatusCode::FOUND, headers).into_response()
+        Ok((StatusCode::FOUND, headers).into_response())
     } else {
         let mut headers = HeaderMap::new();
         headers.insert(
             "Set-Cookie",
-            format!(
-                "session={}; Max-Age=3600; Path=/; SameSite=None; Secure; HttpOnly",
-                session_id
-            )
-            .parse()
-            .unwrap(),
+            session_cookie(&state.config, &session_id, session_ttl),
         );
+        headers.append("Set-Cookie", oauth_state_cookie(&state.config, "", 0));
 
         let response = AuthResponse {
             user,
             session: session_id,
         };
-        (StatusCode::OK, headers, Json(response)).into_response()
+        Ok((StatusCode::OK, headers, Json(response)).into_response())
     }
 }
 
-pub async fn me(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
-    match get_user_from_session(&headers, &state).await {
-        Ok(user) => {
-            let result = sqlx::query("SELECT timezone FROM timezones WHERE user_id = $1")
-                .bind(&user.id)
-                .fetch_optional(&state.db)
-                .await;
-
-            match result {
-                Ok(Some(row)) => {
-                    let timezone: String = row.get("timezone");
-                    (
-                        StatusCode::OK,
-                        Json(serde_json::json!({
-                            "user": user,
-                            "timezone": timezone
-                        })),
-                    )
-                        .into_response()
-                }
-                Ok(None) => (
-                    StatusCode::OK,
-                    Json(serde_json::json!({
-                        "user": user,
-                        "timezone": null
-                    })),
-                )
-                    .into_response(),
-                Err(_) => (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(JsonMessage {
-                        message: "Failed to fetch timezone".into(),
-                    }),
-                )
-                    .into_response(),
-            }
+pub async fn me(State(state): State<AppState>, headers: HeaderMap) -> AppResult<impl IntoResponse> {
+    let user = match get_user_from_session(&headers, &state).await {
+        Ok(user) => user,
+        // Only a missing or invalid session means anonymous; a Redis failure
+        // stays an error rather than looking like a logout.
+        Err(AppError::Unauthorized(_)) if state.config.me_returns_200_when_anon => {
+            return Ok(Json(serde_json::json!({
+                "authenticated": false,
+                "user": null,
+                "timezone": null
+            })));
+        }
+        Err(err) => return Err(err),
+    };
+
+    let record = retry_read(|| state.store.get(&user.id))
+        .await
+        .map_err(AppError::db("Failed to fetch timezone"))?;
+    Ok(Json(serde_json::json!({
+        "authenticated": true,
+        "user": user,
+        "timezone": record.as_ref().map(|record| &record.timezone),
+        "coordinates": record.and_then(|record| record.coordinates())
+    })))
+}
+
+/// `POST /me/refresh`: reloads the session user's Discord profile with the
+/// stored access token, renewing it first if it has expired, and saves the
+/// new username and avatar on the session and the stored entry.
+pub async fn refresh_me(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> AppResult<Json<DiscordUser>> {
+    let (session_id, session) = session_from_headers(&headers, &state).await?;
+    if state.config.discord_token_key.is_none() {
+        return Err(AppError::not_found("Discord token storage is disabled"));
+    }
+    let access_token = discord_tokens::access_token(&state, &session_id)
+        .await
+        .ok_or_else(|| {
+            AppError::unauthorized("Discord authorization expired, please log in again")
+        })?;
+
+    let user = fetch_user(&state, &access_token).await?;
+    if user.id != session.user.id {
+        return Err(AppError::External {
+            message: "Failed to fetch user",
+            detail: "profile belongs to another user".into(),
+        });
+    }
+
+    // Keeps the session's remaining lifetime; one that lapsed meanwhile
+    // isn't brought back.
+    let key = format!("session:{}", session_id);
+    if let Ok(ttl) = u64::try_from(state.kv.ttl(&key).await?) {
+        let session = StoredSession {
+            user: user.clone(),
+            created_at: session.created_at,
+        };
+        state
+            .kv
+            .set_ex(&key, &serde_json::to_string(&session).unwrap(), ttl.max(1))
+            .await?;
+    }
+    refresh_profile(state.clone(), user.clone()).await;
+    Ok(Json(user))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(target: &str) -> bool {
+        allowed_redirect(
+            target,
+            &["app.example.com".into(), "local.test:8080".into()],
+        )
+    }
+
+    #[test]
+    fn redirect_to_a_path_on_this_site() {
+        assert!(allowed("/"));
+        assert!(allowed("/settings?tab=zone#top"));
+    }
+
+    #[test]
+    fn redirect_to_an_allowed_host() {
+        assert!(allowed("https://app.example.com/after-login"));
+        assert!(allowed("http://local.test:8080/"));
+        assert!(!allowed("http://local.test/"));
+        assert!(!allowed("https://evil.example/"));
+        assert!(!allowed("https://app.example.com.evil.example/"));
+        assert!(!allowed("https://app.example.com@evil.example/"));
+    }
+
+    #[test]
+    fn redirect_schemes_other_than_http_are_refused() {
+        assert!(!allowed("javascript:alert(1)"));
+        assert!(!allowed("ftp://app.example.com/"));
+        assert!(!allowed("data:text/html,hi"));
+    }
+
+    #[test]
+    fn scheme_relative_redirects_count_as_absolute() {
+        assert!(!allowed("//evil.example/"));
+        assert!(!allowed("//app.example.com/"));
+        assert!(!allowed("/\\evil.example/"));
+        assert!(!allowed("\\\\evil.example/"));
+        assert!(!allowed("/\tevil.example/"));
+        assert!(!allowed("/\n/evil.example/"));
+    }
+
+    #[test]
+    fn encoded_slashes_stay_a_path() {
+        // Browsers don't decode these before resolving, so the target
+        // stays on this site.
+        assert!(allowed("/%2F%2Fevil.example/"));
+        assert!(allowed("/%5Cevil.example/"));
+        assert!(!allowed("%2F%2Fevil.example/"));
+    }
+
+    #[test]
+    fn over_long_redirects_are_refused() {
+        let path = format!("/{}", "a".repeat(MAX_REDIRECT_LEN - 1));
+        assert!(allowed(&path));
+        assert!(!allowed(&format!("{}a", path)));
+    }
+
+    #[test]
+    fn session_ids_in_each_format() {
+        let uuid = Uuid::now_v7().to_string();
+        assert!(is_session_id(&uuid, SessionIdFormat::Uuidv7));
+        assert!(is_session_id(&new_ulid(), SessionIdFormat::Ulid));
+        let random = new_session_id(SessionIdFormat::Random128);
+        assert!(is_session_id(&random, SessionIdFormat::Random128));
+
+        assert!(!is_session_id(&uuid, SessionIdFormat::Ulid));
+        assert!(!is_session_id(&random, SessionIdFormat::Uuidv7));
+    }
+
+    #[test]
+    fn session_ids_only_in_their_canonical_spelling() {
+        let uuid = Uuid::now_v7();
+        assert!(!is_session_id(
+            &uuid.to_string().to_uppercase(),
+            SessionIdFormat::Uuidv7
+        ));
+        assert!(!is_session_id(
+            &uuid.simple().to_string(),
+            SessionIdFormat::Uuidv7
+        ));
+        assert!(!is_session_id(
+            &uuid.braced().to_string(),
+            SessionIdFormat::Uuidv7
+        ));
+        assert!(!is_session_id(
+            &Uuid::new_v4().to_string(),
+            SessionIdFormat::Uuidv7
+        ));
+
+        let ulid = new_ulid();
+        assert!(!is_session_id(&ulid.to_lowercase(), SessionIdFormat::Ulid));
+        // Past the 128 bits a ULID holds.
+        assert!(!is_session_id(
+            &format!("8{}", &ulid[1..]),
+            SessionIdFormat::Ulid
+        ));
+        // I, L, O and U aren't in Crockford's alphabet.
+        assert!(!is_session_id(
+            &format!("{}I", &ulid[..25]),
+            SessionIdFormat::Ulid
+        ));
+
+        let random = new_session_id(SessionIdFormat::Random128);
+        assert!(!is_session_id(
+            &random.to_uppercase(),
+            SessionIdFormat::Random128
+        ));
+    }
+
+    #[test]
+    fn session_ids_of_the_wrong_length_are_refused() {
+        for format in [
+            SessionIdFormat::Uuidv7,
+            SessionIdFormat::Ulid,
+            SessionIdFormat::Random128,
+        ] {
+            let id = new_session_id(format);
+            assert!(!is_session_id("", format));
+            assert!(!is_session_id(&id[1..], format));
+            assert!(!is_session_id(&format!("{}0", id), format));
+            assert!(!is_session_id(&"0".repeat(4096), format));
         }
-        Err(err) => err.into_response(),
     }
 }
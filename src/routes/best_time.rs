@@ -0,0 +1,355 @@
+use crate::db::AppState;
+use crate::error::{AppError, AppResult};
+use crate::routes::overlap::{load_zones, requested_ids};
+use crate::routes::user_ids;
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono::{DateTime, Duration, DurationRound, NaiveTime, Offset, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// Candidate meetings start on this grid, in minutes.
+const SLOT_STEP_MINUTES: i64 = 15;
+
+/// How far ahead candidates are searched, in minutes.
+const SEARCH_MINUTES: i64 = 24 * 60;
+
+/// Longest meeting that may be asked for, in minutes.
+const MAX_DURATION_MINUTES: i64 = 12 * 60;
+
+/// Candidates returned at most.
+const MAX_CANDIDATES: usize = 5;
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+#[derive(Deserialize)]
+pub struct BestTimeBody {
+    #[serde(deserialize_with = "user_ids")]
+    ids: Vec<String>,
+    window_start: String,
+    window_end: String,
+    duration_minutes: i64,
+}
+
+#[derive(Serialize)]
+struct LocalSlot {
+    id: String,
+    start: String,
+    end: String,
+}
+
+#[derive(Serialize)]
+struct Candidate {
+    start: String,
+    end: String,
+    /// Minutes between the meeting and the nearest window edge, for the user
+    /// closest to one.
+    min_margin_minutes: i64,
+    local: Vec<LocalSlot>,
+}
+
+#[derive(Serialize)]
+struct BestTimeResponse {
+    candidates: Vec<Candidate>,
+    /// Requested ids with no stored timezone; they are not considered.
+    missing: Vec<String>,
+}
+
+/// Suggests meeting slots within the next 24 hours where every user is inside
+/// their local window for the whole meeting.
+///
+/// Slots start every `SLOT_STEP_MINUTES` and are checked in each user's zone,
+/// coping with overnight windows and DST changes inside the meeting.
+/// Fitting slots are ranked by their smallest margin to any user's window
+/// edge, so nobody is pushed to the very start or end of their day, then by
+/// the total margin, then by how soon they are. Candidates never overlap.
+pub async fn best_time(
+    State(state): State<AppState>,
+    Json(body): Json<BestTimeBody>,
+) -> AppResult<impl IntoResponse> {
+    let ids = requested_ids(&body.ids).map_err(AppError::bad_request)?;
+
+    let parse = |value: &str| NaiveTime::parse_from_str(value.trim(), "%H:%M");
+    let (Ok(start), Ok(end)) = (parse(&body.window_start), parse(&body.window_end)) else {
+        return Err(AppError::bad_request(
+            "window_start and window_end must be 24-hour HH:MM times",
+        ));
+    };
+    if start == end {
+        return Err(AppError::bad_request(
+            "window_start and window_end must differ",
+        ));
+    }
+    if !(1..=MAX_DURATION_MINUTES).contains(&body.duration_minutes) {
+        return Err(AppError::bad_request(format!(
+            "duration_minutes must be between 1 and {}",
+            MAX_DURATION_MINUTES
+        )));
+    }
+
+    let mut found = load_zones(&state, &ids).await?;
+    let mut users = Vec::new();
+    let mut missing = Vec::new();
+    for id in ids {
+        match found.remove(&id) {
+            Some((_, _, tz)) => users.push((id, tz)),
+            None => missing.push(id),
+        }
+    }
+
+    let candidates = if users.is_empty() {
+        Vec::new()
+    } else {
+        rank(&users, Utc::now(), start, end, body.duration_minutes)
+    };
+
+    Ok(Json(BestTimeResponse {
+        candidates,
+        missing,
+    }))
+}
+
+fn rank(
+    users: &[(String, Tz)],
+    now: DateTime<Utc>,
+    start: NaiveTime,
+    end: NaiveTime,
+    duration: i64,
+) -> Vec<Candidate> {
+    let step = Duration::minutes(SLOT_STEP_MINUTES);
+    let first = now
+        .duration_trunc(step)
+        .map(|at| if at < now { at + step } else { at })
+        .unwrap_or(now);
+
+    // (min margin, total margin, slot start) for every slot that fits.
+    let mut fitting: Vec<(i64, i64, DateTime<Utc>)> = (0..SEARCH_MINUTES / SLOT_STEP_MINUTES)
+        .map(|n| first + step * n as i32)
+        .filter_map(|at| {
+            let margins = users
+                .iter()
+                .map(|(_, tz)| margin(*tz, at, start, end, duration))
+                .collect::<Option<Vec<i64>>>()?;
+            let min = margins.iter().copied().min()?;
+            Some((min, margins.iter().sum(), at))
+        })
+        .collect();
+    fitting.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+
+    let length = Duration::minutes(duration);
+    let mut chosen: Vec<(i64, DateTime<Utc>)> = Vec::new();
+    for (min, _, at) in fitting {
+        if chosen.len() == MAX_CANDIDATES {
+            break;
+        }
+        if chosen
+            .iter()
+            .all(|(_, other)| at + length <= *other || *other + length <= at)
+        {
+            chosen.push((min, at));
+        }
+    }
+
+    let format = |at: DateTime<Utc>| at.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    chosen
+        .into_iter()
+        .map(|(min_margin_minutes, at)| Candidate {
+            start: format(at),
+            end: format(at + length),
+            min_margin_minutes,
+            local: users
+                .iter()
+                .map(|(id, tz)| LocalSlot {
+                    id: id.clone(),
+                    start: at.with_timezone(tz).format("%H:%M").to_string(),
+                    end: (at + length).with_timezone(tz).format("%H:%M").to_string(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Minutes between the meeting and the nearer edge of the user's window, or
+/// `None` if any minute of it falls outside the window.
+///
+/// Local time only jumps when the zone's offset changes, which happens at most
+/// once in the 12 hours a meeting may last. So the meeting is at most two
+/// stretches of steady local time, and each fits if it starts far enough
+/// inside the window to last until its end. Only the meeting's first and last
+/// minutes are converted, plus a bisection for the change when they differ.
+fn margin(
+    tz: Tz,
+    at: DateTime<Utc>,
+    start: NaiveTime,
+    end: NaiveTime,
+    duration: i64,
+) -> Option<i64> {
+    let minute_of_day = |time: NaiveTime| i64::from(time.num_seconds_from_midnight() / 60);
+    let local = |offset: i64| (at + Duration::minutes(offset)).with_timezone(&tz);
+    let utc_offset = |offset: i64| local(offset).offset().fix();
+    let (open, close) = (minute_of_day(start), minute_of_day(end));
+    let length = (close - open).rem_euclid(MINUTES_PER_DAY);
+    let into_window =
+        |offset: i64| (minute_of_day(local(offset).time()) - open).rem_euclid(MINUTES_PER_DAY);
+    // Minutes `from..to` of the meeting, all at one offset.
+    let fits = |from: i64, to: i64| into_window(from) + (to - from) <= length;
+
+    let last = duration - 1;
+    let fit = if utc_offset(0) == utc_offset(last) {
+        fits(0, duration)
+    } else {
+        // The first minute at the new offset lies in `(low, high]`.
+        let (mut low, mut high) = (0, last);
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            if utc_offset(mid) == utc_offset(0) {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        fits(0, high) && fits(high, duration)
+    };
+    if !fit {
+        return None;
+    }
+
+    let before = into_window(0);
+    let after = (close - minute_of_day(local(duration).time())).rem_euclid(MINUTES_PER_DAY);
+    Some(before.min(after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::overlap::in_window;
+
+    fn time(value: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(value, "%H:%M").unwrap()
+    }
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().to_utc()
+    }
+
+    /// The definition `margin` shortcuts: every minute checked on its own.
+    fn every_minute_fits(
+        tz: Tz,
+        at: DateTime<Utc>,
+        start: NaiveTime,
+        end: NaiveTime,
+        duration: i64,
+    ) -> bool {
+        (0..duration).all(|m| {
+            in_window(
+                (at + Duration::minutes(m)).with_timezone(&tz).time(),
+                start,
+                end,
+            )
+        })
+    }
+
+    #[test]
+    fn margin_matches_a_minute_by_minute_check() {
+        let zones = [
+            chrono_tz::America::New_York,
+            chrono_tz::Europe::London,
+            chrono_tz::Australia::Lord_Howe,
+            chrono_tz::Asia::Kathmandu,
+            chrono_tz::UTC,
+        ];
+        let windows = [("09:00", "17:00"), ("22:00", "06:00"), ("00:30", "23:45")];
+        // Days with a spring-forward and a fall-back change in New York and
+        // London, and Lord Howe's half-hour change.
+        let days = [
+            "2025-04-05T12:00:00Z",
+            "2025-03-09T00:00:00Z",
+            "2025-03-30T00:00:00Z",
+            "2025-10-26T00:00:00Z",
+            "2025-11-02T00:00:00Z",
+        ];
+        for tz in zones {
+            for (start, end) in windows {
+                let (start, end) = (time(start), time(end));
+                for day in days {
+                    for slot in 0..SEARCH_MINUTES / SLOT_STEP_MINUTES {
+                        let at = utc(day) + Duration::minutes(slot * SLOT_STEP_MINUTES);
+                        for duration in [15, 60, 150, MAX_DURATION_MINUTES] {
+                            assert_eq!(
+                                margin(tz, at, start, end, duration).is_some(),
+                                every_minute_fits(tz, at, start, end, duration),
+                                "{} {} {}-{} for {} minutes",
+                                tz,
+                                at,
+                                start,
+                                end,
+                                duration
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn margin_is_distance_to_the_nearer_edge() {
+        let at = utc("2025-06-02T10:00:00Z");
+        let (start, end) = (time("09:00"), time("17:00"));
+        assert_eq!(margin(chrono_tz::UTC, at, start, end, 60), Some(60));
+        assert_eq!(
+            margin(chrono_tz::UTC, utc("2025-06-02T15:00:00Z"), start, end, 60),
+            Some(60)
+        );
+        assert_eq!(
+            margin(chrono_tz::UTC, utc("2025-06-02T16:00:00Z"), start, end, 60),
+            Some(0)
+        );
+        assert_eq!(
+            margin(chrono_tz::UTC, utc("2025-06-02T16:15:00Z"), start, end, 60),
+            None
+        );
+    }
+
+    #[test]
+    fn overnight_window_fits_across_midnight() {
+        let (start, end) = (time("22:00"), time("02:00"));
+        let at = utc("2025-06-02T23:00:00Z");
+        assert_eq!(margin(chrono_tz::UTC, at, start, end, 120), Some(60));
+        assert_eq!(margin(chrono_tz::UTC, at, start, end, 240), None);
+    }
+
+    #[test]
+    fn rank_prefers_the_middle_of_everyones_window_without_overlap() {
+        let users = vec![
+            ("a".to_string(), chrono_tz::UTC),
+            ("b".to_string(), chrono_tz::Europe::Berlin),
+        ];
+        // Berlin is UTC+2 in June, so the shared day is 09:00-15:00 UTC.
+        let now = utc("2025-06-02T00:00:00Z");
+        let candidates = rank(&users, now, time("09:00"), time("17:00"), 60);
+
+        assert_eq!(candidates.len(), MAX_CANDIDATES);
+        assert_eq!(candidates[0].start, "2025-06-02T11:30:00Z");
+        assert_eq!(candidates[0].end, "2025-06-02T12:30:00Z");
+        assert_eq!(candidates[0].min_margin_minutes, 150);
+        assert_eq!(candidates[0].local[1].start, "13:30");
+        for pair in candidates.windows(2) {
+            assert!(pair[0].min_margin_minutes >= pair[1].min_margin_minutes);
+        }
+        for (i, a) in candidates.iter().enumerate() {
+            for b in &candidates[i + 1..] {
+                assert!(a.end <= b.start || b.end <= a.start);
+            }
+        }
+    }
+
+    #[test]
+    fn rank_finds_nothing_when_windows_never_meet() {
+        let users = vec![
+            ("a".to_string(), chrono_tz::America::Los_Angeles),
+            ("b".to_string(), chrono_tz::Asia::Kolkata),
+        ];
+        let now = utc("2025-06-02T00:00:00Z");
+        assert!(rank(&users, now, time("09:00"), time("12:00"), 60).is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::db::AppState;
+use crate::error::{AppError, AppResult};
+use crate::metrics;
+use crate::routes::{comma_separated, info::load_profile};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+/// The format letters Discord accepts in `<t:unix:style>`.
+const STYLES: &[&str] = &["t", "T", "d", "D", "f", "F", "R"];
+
+#[derive(Deserialize)]
+pub struct TimestampQuery {
+    id: String,
+    #[serde(default, deserialize_with = "comma_separated")]
+    style: Vec<String>,
+    /// `HH:MM` on the user's current local date; defaults to now.
+    at: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TimestampResponse {
+    id: String,
+    timezone: String,
+    unix: i64,
+    markup: BTreeMap<String, String>,
+}
+
+/// Discord timestamp markup for a user's current time, or for a wall-clock
+/// time later today in their zone (`at=09:00`), so bots can say "9am for
+/// them" and have every viewer see it in their own time.
+pub async fn discord_timestamp(
+    State(state): State<AppState>,
+    Query(query): Query<TimestampQuery>,
+) -> AppResult<impl IntoResponse> {
+    let started = Instant::now();
+    let id = query.id.trim();
+    if id.is_empty() {
+        return Err(AppError::bad_request("A user ID is required"));
+    }
+
+    let styles = if query.style.is_empty() {
+        vec!["f".to_string()]
+    } else {
+        query.style
+    };
+    if let Some(bad) = styles.iter().find(|s| !STYLES.contains(&s.as_str())) {
+        return Err(AppError::bad_request(format!(
+            "Unknown style {:?}; expected one of {}",
+            bad,
+            STYLES.join(", ")
+        )));
+    }
+
+    let (profile, tz) = load_profile(&state, id).await?;
+
+    let now = Utc::now();
+    let unix = match query.at.as_deref() {
+        None => now.timestamp(),
+        Some(at) => {
+            let time = NaiveTime::parse_from_str(at.trim(), "%H:%M")
+                .map_err(|_| AppError::bad_request("at must be a 24-hour HH:MM time"))?;
+            let local = now.with_timezone(&tz).date_naive().and_time(time);
+            tz.from_local_datetime(&local)
+                .earliest()
+                .ok_or_else(|| {
+                    AppError::bad_request("That time is skipped by a clock change today")
+                })?
+                .timestamp()
+        }
+    };
+
+    let markup = styles
+        .into_iter()
+        .map(|style| {
+            let tag = format!("<t:{}:{}>", unix, style);
+            (style, tag)
+        })
+        .collect();
+
+    let response = TimestampResponse {
+        id: id.to_string(),
+        timezone: profile.timezone,
+        unix,
+        markup,
+    };
+    metrics::observe_zone_latency(tz, started.elapsed());
+    Ok(Json(response))
+}
@@ -0,0 +1,42 @@
+use crate::db::AppState;
+use crate::middleware::field_case::to_json;
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::{stream, Stream};
+use serde_json::json;
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
+
+/// A Server-Sent Events stream of timezone changes. Each `timezone` event
+/// carries the same JSON as a webhook delivery, with the nonce as its event
+/// ID. A client too slow to keep up gets a `lagged` event with the number
+/// it missed, and should reload `/list` to catch up. Keys follow
+/// `API_FIELD_CASE` like every other JSON response.
+pub async fn events(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let (receiver, shutdown) = state.events.subscribe();
+    let case = state.config.api_field_case;
+    let stream = stream::unfold(
+        (receiver, shutdown),
+        move |(mut receiver, mut shutdown)| async move {
+            let event = tokio::select! {
+                event = receiver.recv() => match event {
+                    Ok(change) => Event::default()
+                        .event("timezone")
+                        .id(change.nonce.clone())
+                        .data(to_json(&change, case).ok()?),
+                    Err(RecvError::Lagged(missed)) => Event::default()
+                        .event("lagged")
+                        .data(to_json(&json!({ "missed": missed }), case).ok()?),
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = shutdown.changed() => return None,
+            };
+            Some((Ok(event), (receiver, shutdown)))
+        },
+    );
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,126 @@
+use crate::db::{store::ExportRecord, AppState};
+use crate::error::AppResult;
+use crate::middleware::api_key::{require_scope, ApiKey, ApiScope};
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::IntoResponse,
+    Extension,
+};
+use futures_util::{stream, StreamExt};
+use std::io;
+use tokio::sync::mpsc;
+use tracing::error;
+
+const HEADER: &str =
+    "user_id,username,timezone,avatar,time_format,latitude,longitude,updated_at\r\n";
+
+/// Rows are sent in chunks of about this many bytes.
+const CHUNK_LEN: usize = 16 * 1024;
+
+/// Chunks buffered ahead of a slow client before the query waits for it.
+const BUFFERED_CHUNKS: usize = 4;
+
+/// Streams every stored entry as CSV. Rows are read from a database cursor and
+/// written out as they arrive, so memory use doesn't grow with the table. A
+/// database error partway through aborts the response, which the client sees
+/// as a truncated download rather than a file that looks complete.
+pub async fn export_csv(
+    State(state): State<AppState>,
+    api_key: Option<Extension<ApiKey>>,
+) -> AppResult<impl IntoResponse> {
+    require_scope(api_key.as_deref(), ApiScope::Admin)?;
+
+    let store = state.store.clone();
+    let (tx, rx) = mpsc::channel::<Result<Bytes, io::Error>>(BUFFERED_CHUNKS);
+    tokio::spawn(async move {
+        let mut rows = store.export();
+        let mut chunk = String::from(HEADER);
+        while let Some(row) = rows.next().await {
+            match row {
+                Ok(row) => write_row(&mut chunk, &row),
+                Err(err) => {
+                    error!("CSV export failed: {}", err);
+                    tx.send(Err(io::Error::other("export failed"))).await.ok();
+                    return;
+                }
+            }
+            if chunk.len() >= CHUNK_LEN {
+                let full = std::mem::take(&mut chunk);
+                if tx.send(Ok(Bytes::from(full))).await.is_err() {
+                    // The client went away.
+                    return;
+                }
+            }
+        }
+        if !chunk.is_empty() {
+            tx.send(Ok(Bytes::from(chunk))).await.ok();
+        }
+    });
+
+    let body = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+    Ok((
+        [
+            (CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                CONTENT_DISPOSITION,
+                "attachment; filename=\"timezones.csv\"",
+            ),
+        ],
+        Body::from_stream(body),
+    ))
+}
+
+fn write_row(out: &mut String, row: &ExportRecord) {
+    let record = &row.record;
+    let latitude = record.latitude.map(|lat| lat.to_string());
+    let longitude = record.longitude.map(|lon| lon.to_string());
+    // (field, whether it holds user-supplied text)
+    let fields = [
+        (Some(record.user_id.as_str()), true),
+        (Some(record.username.as_str()), true),
+        (Some(record.timezone.as_str()), true),
+        (record.avatar.as_deref(), true),
+        (record.time_format.as_deref(), true),
+        (latitude.as_deref(), false),
+        (longitude.as_deref(), false),
+        (Some(row.updated_at.as_str()), false),
+    ];
+    for (i, (field, text)) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if let Some(field) = field {
+            push_field(out, field, *text);
+        }
+    }
+    out.push_str("\r\n");
+}
+
+/// Leading characters that make spreadsheets read a cell as a formula.
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// RFC 4180 quoting: fields with a comma, quote or line break are wrapped in
+/// quotes, with quotes inside doubled. Text that would start with a formula
+/// character gets a leading `'` first, so a username like `=HYPERLINK(...)`
+/// opens as text rather than running. Numbers are never prefixed, so
+/// negative coordinates stay numeric.
+fn push_field(out: &mut String, field: &str, text: bool) {
+    let escaped;
+    let field = if text && field.starts_with(FORMULA_PREFIXES) {
+        escaped = format!("'{}", field);
+        escaped.as_str()
+    } else {
+        field
+    };
+    if field.contains([',', '"', '\r', '\n']) {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(field);
+    }
+}
@@ -0,0 +1,55 @@
+use crate::db::AppState;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use std::future::Future;
+use std::time::Duration;
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ProbeStatus {
+    Ok,
+    Error,
+    Timeout,
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+    healthy: bool,
+    database: ProbeStatus,
+    redis: ProbeStatus,
+}
+
+async fn probe(timeout: Duration, check: impl Future<Output = bool>) -> ProbeStatus {
+    match tokio::time::timeout(timeout, check).await {
+        Ok(true) => ProbeStatus::Ok,
+        Ok(false) => ProbeStatus::Error,
+        Err(_) => ProbeStatus::Timeout,
+    }
+}
+
+/// Probes the database and Redis concurrently, each under `HEALTH_TIMEOUT`, so the
+/// endpoint answers within that bound even when a dependency hangs. Any probe
+/// that fails or times out makes the response a 503. Sessions kept in process
+/// always report `redis` as ok.
+pub async fn health(State(state): State<AppState>) -> impl IntoResponse {
+    let timeout = state.config.health_timeout;
+    let (database, redis) = tokio::join!(
+        probe(timeout, async { state.store.ping().await.is_ok() }),
+        probe(timeout, async { state.kv.ping().await.is_ok() }),
+    );
+
+    let healthy = database == ProbeStatus::Ok && redis == ProbeStatus::Ok;
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(HealthResponse {
+            healthy,
+            database,
+            redis,
+        }),
+    )
+}
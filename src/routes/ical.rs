@@ -0,0 +1,118 @@
+use crate::db::AppState;
+use crate::error::{AppError, AppResult};
+use crate::metrics;
+use crate::routes::info::load_profile;
+use crate::tz::{self, OffsetInfo};
+use axum::{
+    extract::{Query, State},
+    http::header::CONTENT_TYPE,
+    response::IntoResponse,
+};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::fmt::Write;
+use std::time::Instant;
+
+/// Years of transitions listed, starting with the current one.
+const YEARS_COVERED: i32 = 2;
+
+#[derive(Deserialize)]
+pub struct IcalQuery {
+    id: String,
+}
+
+/// The user's zone as an iCalendar `VTIMEZONE`, for importing into calendar
+/// apps.
+pub async fn get_ical(
+    State(state): State<AppState>,
+    Query(query): Query<IcalQuery>,
+) -> AppResult<impl IntoResponse> {
+    let started = Instant::now();
+    let id = query.id.trim();
+    if id.is_empty() {
+        return Err(AppError::bad_request("A user ID is required"));
+    }
+
+    let (_, tz) = load_profile(&state, id).await?;
+
+    let body = calendar(tz, Utc::now().year());
+    metrics::observe_zone_latency(tz, started.elapsed());
+    Ok(([(CONTENT_TYPE, "text/calendar; charset=utf-8")], body))
+}
+
+/// A `VCALENDAR` holding one `VTIMEZONE` with every transition from the
+/// start of `year` through `YEARS_COVERED` years. Each transition is listed
+/// with an explicit `DTSTART` rather than an `RRULE`, which is valid for any
+/// zone and needs nothing from chrono-tz it doesn't expose.
+fn calendar(tz: Tz, year: i32) -> String {
+    let year_start = |year| {
+        NaiveDate::from_ymd_opt(year, 1, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|at| at.and_utc())
+            .unwrap_or_else(Utc::now)
+    };
+    let start = year_start(year);
+    let end = year_start(year + YEARS_COVERED);
+
+    let mut out = String::new();
+    let mut line = |text: String| {
+        out.push_str(&text);
+        out.push_str("\r\n");
+    };
+    line("BEGIN:VCALENDAR".into());
+    line("VERSION:2.0".into());
+    line("PRODID:-//timezoneDB//EN".into());
+    line("BEGIN:VTIMEZONE".into());
+    line(format!("TZID:{}", tz.name()));
+
+    let mut previous = tz::offset_at(tz, start);
+    line(component(start, &previous, &previous));
+    let mut cursor = start;
+    while let Some(at) = tz::transition_after(tz, cursor) {
+        if at >= end {
+            break;
+        }
+        let offset = tz::offset_at(tz, at);
+        line(component(at, &previous, &offset));
+        previous = offset;
+        // Step past the change itself, or the search would find it again.
+        cursor = at + Duration::seconds(1);
+    }
+
+    line("END:VTIMEZONE".into());
+    line("END:VCALENDAR".into());
+    out
+}
+
+/// One `STANDARD` or `DAYLIGHT` block. `DTSTART` is in the local time in
+/// effect before the change, as RFC 5545 requires.
+fn component(at: DateTime<Utc>, from: &OffsetInfo, to: &OffsetInfo) -> String {
+    let kind = if to.dst { "DAYLIGHT" } else { "STANDARD" };
+    let local = at + Duration::seconds(from.utc_offset_seconds.into());
+    let mut block = String::new();
+    let _ = write!(
+        block,
+        "BEGIN:{kind}\r\nDTSTART:{}\r\nTZOFFSETFROM:{}\r\nTZOFFSETTO:{}",
+        local.format("%Y%m%dT%H%M%S"),
+        ical_offset(from.utc_offset_seconds),
+        ical_offset(to.utc_offset_seconds),
+    );
+    if let Some(name) = &to.abbreviation {
+        let _ = write!(block, "\r\nTZNAME:{}", name);
+    }
+    let _ = write!(block, "\r\nEND:{kind}");
+    block
+}
+
+/// Formats an offset as iCalendar's `+HHMM`, with seconds only when needed.
+fn ical_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let total = seconds.unsigned_abs();
+    let (hours, minutes, secs) = (total / 3600, total / 60 % 60, total % 60);
+    if secs == 0 {
+        format!("{}{:02}{:02}", sign, hours, minutes)
+    } else {
+        format!("{}{:02}{:02}{:02}", sign, hours, minutes, secs)
+    }
+}
@@ -0,0 +1,199 @@
+use crate::db::{
+    redis_helper::{cache_get, cache_set, ttl_with_jitter},
+    store::retry_read,
+    AppState,
+};
+use crate::error::{AppError, AppResult};
+use crate::metrics;
+use crate::routes::{auth::avatar_url, comma_separated};
+use crate::tz::{self, Formats, OffsetInfo, RegionHint, TimeFormat, Transition};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{Datelike, Duration, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[derive(Deserialize)]
+pub struct InfoQuery {
+    id: String,
+    #[serde(default, deserialize_with = "comma_separated")]
+    include: Vec<String>,
+    /// Overrides the user's stored time format for this request.
+    fmt: Option<String>,
+    /// Signed duration (`3h`, `-30m`) to also report the local time at.
+    #[serde(rename = "in")]
+    shift: Option<String>,
+}
+
+/// The parts of `/info` that only change when the user does; cached in Redis.
+#[derive(Serialize, Deserialize)]
+pub(super) struct CachedProfile {
+    pub username: String,
+    pub avatar_url: String,
+    pub timezone: String,
+    #[serde(default)]
+    pub time_format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LocalTime {
+    iso: String,
+    formatted: String,
+    format: &'static str,
+}
+
+#[derive(Serialize)]
+struct InfoResponse {
+    id: String,
+    username: String,
+    avatar_url: String,
+    timezone: String,
+    canonical_timezone: String,
+    local_time: LocalTime,
+    observes_dst: bool,
+    #[serde(flatten)]
+    offset: OffsetInfo,
+    next_transition: Option<Transition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<RegionHint>,
+    /// Every format at once, with `include=formats`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    formats: Option<Formats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shifted: Option<ShiftedTime>,
+}
+
+/// The user's local time `in` from now. The shift is applied to the instant,
+/// so a DST change in between moves the wall clock by more or less than the
+/// shift itself.
+#[derive(Serialize)]
+struct ShiftedTime {
+    #[serde(rename = "in")]
+    shift: String,
+    local_time: LocalTime,
+    #[serde(flatten)]
+    offset: OffsetInfo,
+    crosses_transition: bool,
+}
+
+pub fn info_cache_key(user_id: &str) -> String {
+    format!("info:{}", user_id)
+}
+
+/// Everything a profile card needs in one call.
+pub async fn get_info(
+    State(state): State<AppState>,
+    Query(query): Query<InfoQuery>,
+) -> AppResult<impl IntoResponse> {
+    let started = Instant::now();
+    let id = query.id.trim();
+    if id.is_empty() {
+        return Err(AppError::bad_request("A user ID is required"));
+    }
+
+    let shift = query
+        .shift
+        .as_deref()
+        .map(tz::parse_shift)
+        .transpose()
+        .map_err(AppError::bad_request)?;
+
+    let (profile, tz) = load_profile(&state, id).await?;
+
+    let format = match query.fmt.as_deref().or(profile.time_format.as_deref()) {
+        // A stored preset that no longer exists falls back to the default.
+        Some(name) => match name.parse::<TimeFormat>() {
+            Ok(format) => format,
+            Err(message) if query.fmt.is_some() => return Err(AppError::bad_request(message)),
+            Err(_) => TimeFormat::default(),
+        },
+        None => TimeFormat::default(),
+    };
+
+    let now = Utc::now();
+    let local = now.with_timezone(&tz);
+    let offset = tz::offset_at(tz, now);
+
+    let shifted = shift.map(|secs| {
+        let at = now + Duration::seconds(secs);
+        let local = at.with_timezone(&tz);
+        let shifted_offset = tz::offset_at(tz, at);
+        ShiftedTime {
+            shift: query.shift.clone().unwrap_or_default(),
+            local_time: LocalTime {
+                iso: local.to_rfc3339(),
+                formatted: format.render(&local),
+                format: format.as_str(),
+            },
+            crosses_transition: shifted_offset != offset,
+            offset: shifted_offset,
+        }
+    });
+
+    let response = InfoResponse {
+        id: id.to_string(),
+        username: profile.username,
+        avatar_url: profile.avatar_url,
+        canonical_timezone: tz::canonical(tz).name().to_string(),
+        timezone: profile.timezone,
+        local_time: LocalTime {
+            iso: local.to_rfc3339(),
+            formatted: format.render(&local),
+            format: format.as_str(),
+        },
+        observes_dst: tz::observes_dst(tz, now.year()),
+        offset,
+        next_transition: tz::next_transition(tz, now),
+        region: query
+            .include
+            .iter()
+            .any(|part| part == "flag")
+            .then(|| tz::region_hint(tz))
+            .flatten(),
+        formats: query
+            .include
+            .iter()
+            .any(|part| part == "formats")
+            .then(|| tz::formats(&local)),
+        shifted,
+    };
+    metrics::observe_zone_latency(tz, started.elapsed());
+    Ok(Json(response))
+}
+
+/// Fetches the cacheable part of a user's profile along with their parsed
+/// timezone, from Redis when possible and Postgres otherwise. Misses read the
+/// primary: writes drop this cache entry, and refilling it from a lagging
+/// replica would pin the old value for the whole TTL.
+pub(super) async fn load_profile(state: &AppState, id: &str) -> AppResult<(CachedProfile, Tz)> {
+    let key = info_cache_key(id);
+    let profile = match cache_get::<CachedProfile>(&state.kv, &key).await {
+        Some(profile) => profile,
+        None => {
+            let record = retry_read(|| state.store.get(id))
+                .await?
+                .ok_or_else(|| AppError::not_found("User not found"))?;
+
+            let profile = CachedProfile {
+                avatar_url: avatar_url(id, record.avatar.as_deref()),
+                username: record.username,
+                timezone: record.timezone,
+                time_format: record.time_format,
+            };
+            let ttl = ttl_with_jitter(state.config.info_cache_ttl, state.config.ttl_jitter_pct);
+            cache_set(&state.kv, &key, &profile, ttl).await;
+            profile
+        }
+    };
+
+    let tz = profile
+        .timezone
+        .parse::<Tz>()
+        .map_err(|_| AppError::Internal("Stored timezone is not recognised".into()))?;
+
+    Ok((profile, tz))
+}
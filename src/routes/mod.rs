@@ -1,38 +1,108 @@
+use crate::config::Config;
 use crate::db::AppState;
+use crate::error::AppError;
+use crate::metrics;
+use crate::middleware::cache::no_store;
 use axum::{
-    http::{HeaderValue, StatusCode},
-    response::{Html, Response},
-    routing::{get, options},
-    Router,
+    extract::DefaultBodyLimit,
+    http::{header::CONTENT_TYPE, StatusCode},
+    middleware::map_response,
+    response::{Html, IntoResponse},
+    routing::{any, delete, get, post, MethodRouter},
+    Json, Router,
 };
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fs;
 use tower_http::services::ServeDir;
+use tracing::warn;
 
+mod admin;
+mod at_hour;
 pub mod auth;
+mod best_time;
+mod discord;
+mod events;
+mod export;
+mod health;
+mod ical;
+mod info;
+mod now;
+mod offset;
+mod overlap;
+mod same_zone;
 mod timezone;
+mod zones;
 
-async fn preflight_handler() -> Response {
-    let mut res = Response::new("".into());
+/// Deserializes `a,b,,c` into `["a", "b", "c"]`, dropping blanks and
+/// repeats.
+fn comma_separated<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = String::deserialize(deserializer)?;
+    let mut ids: Vec<String> = Vec::new();
+    for id in raw.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        if !ids.iter().any(|seen| seen == id) {
+            ids.push(id.to_string());
+        }
+    }
+    Ok(ids)
+}
+
+/// A user ID as clients send it: Discord snowflakes arrive as JSON numbers
+/// from some libraries and as strings from others.
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a user ID as a string or non-negative integer")]
+enum RawUserId {
+    String(String),
+    Number(u64),
+}
+
+impl From<RawUserId> for String {
+    fn from(raw: RawUserId) -> Self {
+        match raw {
+            RawUserId::String(id) => id,
+            RawUserId::Number(id) => id.to_string(),
+        }
+    }
+}
+
+/// Accepts a user ID given as either a JSON string or number.
+fn user_id<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    RawUserId::deserialize(deserializer).map(String::from)
+}
 
-    let headers = res.headers_mut();
-    headers.insert("access-control-allow-origin", HeaderValue::from_static("*"));
-    headers.insert(
-        "access-control-allow-methods",
-        HeaderValue::from_static("GET, POST, OPTIONS"),
-    );
-    headers.insert(
-        "access-control-allow-headers",
-        HeaderValue::from_static("Content-Type, Authorization"),
-    );
-    headers.insert(
-        "access-control-allow-credentials",
-        HeaderValue::from_static("true"),
-    );
-    headers.insert("vary", HeaderValue::from_static("Origin"));
+/// Like `user_id`, for optional fields; pair with `#[serde(default)]`.
+fn optional_user_id<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<RawUserId>::deserialize(deserializer).map(|id| id.map(String::from))
+}
 
-    *res.status_mut() = StatusCode::OK;
+/// Like `user_id`, for lists whose entries may mix strings and numbers.
+fn user_ids<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<RawUserId>::deserialize(deserializer)
+        .map(|ids| ids.into_iter().map(String::from).collect())
+}
 
-    res
+/// Answers CORS preflights. `DynamicCors` adds the CORS headers, and only for
+/// allowed origins.
+async fn preflight_handler() -> StatusCode {
+    StatusCode::OK
+}
+
+async fn metrics_page() -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(),
+    )
 }
 
 async fn index_page() -> Html<String> {
@@ -42,17 +112,173 @@ async fn index_page() -> Html<String> {
     )
 }
 
-pub fn all() -> Router<AppState> {
-    Router::new()
-        .route("/", get(index_page))
-        .route("/get", get(timezone::get_timezone))
-        .route("/set", get(timezone::set_timezone))
-        .route("/set", options(preflight_handler))
-        .route("/delete", get(timezone::delete_timezone))
-        .route("/list", get(timezone::list_timezones))
-        .route("/auth/discord", get(auth::start_oauth))
-        .route("/auth/discord/callback", get(auth::handle_callback))
-        .route("/me", get(auth::me))
-        .nest_service("/public", ServeDir::new("public"))
-        .fallback(get(index_page))
+#[derive(Serialize)]
+struct ServiceInfo {
+    service: &'static str,
+    version: &'static str,
+}
+
+/// `/` for API-only deployments (`SERVE_INDEX=false`).
+async fn service_info() -> Json<ServiceInfo> {
+    Json(ServiceInfo {
+        service: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+    })
+}
+
+/// Names accepted by `DISABLED_ROUTES`: the first path segment of each
+/// route, so `admin` covers every `/admin/...` route.
+pub const ROUTE_NAMES: &[&str] = &[
+    "get",
+    "list",
+    "timezones",
+    "info",
+    "discord-timestamp",
+    "ical",
+    "offset-phrase",
+    "overlap",
+    "best-time",
+    "at-hour",
+    "same-zone",
+    "validate",
+    "metrics",
+    "health",
+    "now",
+    "events",
+    "set",
+    "set-by-location",
+    "delete",
+    "auth",
+    "me",
+    "admin",
+];
+
+/// Stands in for every method of a disabled route, so it 404s instead of
+/// falling through to the index page. Also the fallback when the index page
+/// isn't served.
+async fn not_found() -> AppError {
+    AppError::not_found("Not found")
+}
+
+/// A router that serves `not_found` for every method of routes named in
+/// `DISABLED_ROUTES`.
+struct Routes<'a> {
+    router: Router<AppState>,
+    disabled: &'a [String],
+}
+
+impl<'a> Routes<'a> {
+    fn new(disabled: &'a [String]) -> Self {
+        Routes {
+            router: Router::new(),
+            disabled,
+        }
+    }
+
+    fn route(mut self, name: &str, path: &str, handler: MethodRouter<AppState>) -> Self {
+        let handler = if self.disabled.iter().any(|disabled| disabled == name) {
+            any(not_found)
+        } else {
+            handler
+        };
+        self.router = self.router.route(path, handler);
+        self
+    }
+}
+
+pub fn all(config: &Config) -> Router<AppState> {
+    for name in &config.disabled_routes {
+        if !ROUTE_NAMES.contains(&name.as_str()) {
+            warn!(
+                "DISABLED_ROUTES names unknown route {:?}; known routes are {}",
+                name,
+                ROUTE_NAMES.join(", ")
+            );
+        }
+    }
+    let disabled = &config.disabled_routes;
+
+    let authenticated = Routes::new(disabled)
+        .route(
+            "set",
+            "/set",
+            get(timezone::set_timezone)
+                .post(timezone::set_timezone_body)
+                .options(preflight_handler),
+        )
+        .route(
+            "set-by-location",
+            "/set-by-location",
+            post(timezone::set_by_location).options(preflight_handler),
+        )
+        .route("delete", "/delete", get(timezone::delete_timezone))
+        .route("auth", "/auth/discord", get(auth::start_oauth))
+        .route("auth", "/auth/discord/callback", get(auth::handle_callback))
+        .route("me", "/me", get(auth::me))
+        .route(
+            "me",
+            "/me/refresh",
+            post(auth::refresh_me).options(preflight_handler),
+        )
+        .route(
+            "admin",
+            "/admin/keys",
+            get(admin::list_keys).post(admin::create_key),
+        )
+        .route("admin", "/admin/keys/{id}", delete(admin::revoke_key))
+        .route("admin", "/admin/merge", post(admin::merge_users))
+        .route("admin", "/admin/remap", post(admin::remap_timezones))
+        .route("admin", "/admin/export.csv", get(export::export_csv))
+        .route("admin", "/admin/cors/reload", post(admin::reload_cors))
+        .router
+        .layer(map_response(no_store));
+
+    let router = Routes::new(disabled)
+        .route("get", "/get", get(timezone::get_timezone))
+        .route("list", "/list", get(timezone::list_timezones))
+        .route("timezones", "/timezones", get(zones::list_zones))
+        .route("info", "/info", get(info::get_info))
+        .route(
+            "discord-timestamp",
+            "/discord-timestamp",
+            get(discord::discord_timestamp),
+        )
+        .route("ical", "/ical", get(ical::get_ical))
+        .route(
+            "offset-phrase",
+            "/offset-phrase",
+            get(offset::offset_phrase),
+        )
+        .route(
+            "overlap",
+            "/overlap",
+            post(overlap::overlap).options(preflight_handler),
+        )
+        .route(
+            "best-time",
+            "/best-time",
+            post(best_time::best_time).options(preflight_handler),
+        )
+        .route("at-hour", "/at-hour", get(at_hour::at_hour))
+        .route("same-zone", "/same-zone", get(same_zone::same_zone))
+        .route(
+            "validate",
+            "/validate/batch",
+            post(timezone::validate_batch)
+                .layer(DefaultBodyLimit::max(timezone::MAX_VALIDATE_BODY))
+                .options(preflight_handler),
+        )
+        .route("metrics", "/metrics", get(metrics_page))
+        .route("health", "/health", get(health::health))
+        .route("now", "/now", get(now::now))
+        .route("events", "/events", get(events::events))
+        .router
+        .merge(authenticated)
+        .nest_service("/public", ServeDir::new("public"));
+
+    if config.serve_index {
+        router.route("/", get(index_page)).fallback(get(index_page))
+    } else {
+        router.route("/", get(service_info)).fallback(not_found)
+    }
 }
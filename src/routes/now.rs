@@ -0,0 +1,35 @@
+use crate::db::AppState;
+use axum::{extract::State, http::header::CACHE_CONTROL, response::IntoResponse, Json};
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct NowResponse {
+    utc: String,
+    unix: i64,
+    unix_ms: i64,
+    /// `SERVER_TIMEZONE`, if set.
+    timezone: Option<&'static str>,
+    local: Option<String>,
+}
+
+/// The server's clock, so clients can measure their own skew before reading
+/// the times in other responses. Never cached, since a stored copy would be
+/// wrong by its age.
+pub async fn now(State(state): State<AppState>) -> impl IntoResponse {
+    let now = Utc::now();
+    let tz = state.config.server_timezone;
+    (
+        [(CACHE_CONTROL, "no-store")],
+        Json(NowResponse {
+            utc: now.to_rfc3339_opts(SecondsFormat::Millis, true),
+            unix: now.timestamp(),
+            unix_ms: now.timestamp_millis(),
+            timezone: tz.map(|tz| tz.name()),
+            local: tz.map(|tz| {
+                now.with_timezone(&tz)
+                    .to_rfc3339_opts(SecondsFormat::Millis, false)
+            }),
+        }),
+    )
+}
@@ -0,0 +1,51 @@
+use crate::db::AppState;
+use crate::error::{AppError, AppResult};
+use crate::metrics;
+use crate::routes::info::load_profile;
+use crate::tz;
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[derive(Deserialize)]
+pub struct OffsetPhraseQuery {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct OffsetPhraseResponse {
+    id: String,
+    timezone: String,
+    utc_offset_seconds: i32,
+    phrase: String,
+}
+
+/// The user's current offset from UTC in words, e.g. "9 hours ahead of UTC",
+/// so bots don't each have to get the wording and plurals right.
+pub async fn offset_phrase(
+    State(state): State<AppState>,
+    Query(query): Query<OffsetPhraseQuery>,
+) -> AppResult<impl IntoResponse> {
+    let started = Instant::now();
+    let id = query.id.trim();
+    if id.is_empty() {
+        return Err(AppError::bad_request("A user ID is required"));
+    }
+
+    let (profile, tz) = load_profile(&state, id).await?;
+    let offset = tz::offset_at(tz, Utc::now()).utc_offset_seconds;
+
+    let response = OffsetPhraseResponse {
+        id: id.to_string(),
+        timezone: profile.timezone,
+        utc_offset_seconds: offset,
+        phrase: tz::offset_phrase(offset),
+    };
+    metrics::observe_zone_latency(tz, started.elapsed());
+    Ok(Json(response))
+}
@@ -0,0 +1,284 @@
+use crate::db::{store::retry_read, AppState};
+use crate::error::{AppError, AppResult};
+use crate::routes::user_ids;
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono::{DateTime, Duration, DurationRound, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Upper bound on ids accepted by a single `/overlap` call.
+const MAX_OVERLAP_IDS: usize = 50;
+
+/// How far ahead the shared window is searched, in minutes.
+const SEARCH_MINUTES: i64 = 24 * 60;
+
+#[derive(Deserialize)]
+pub struct OverlapBody {
+    #[serde(deserialize_with = "user_ids")]
+    ids: Vec<String>,
+    start: String,
+    end: String,
+}
+
+#[derive(Serialize)]
+struct UserOverlap {
+    id: String,
+    username: String,
+    timezone: String,
+    local_time: String,
+    within: bool,
+}
+
+#[derive(Serialize)]
+struct CommonWindow {
+    start: String,
+    end: String,
+}
+
+#[derive(Serialize)]
+struct OverlapResponse {
+    users: Vec<UserOverlap>,
+    /// Requested ids with no stored timezone.
+    missing: Vec<String>,
+    all_within: bool,
+    within_count: usize,
+    /// The next stretch within 24 hours where everyone is inside the window,
+    /// in UTC, or `null` if there is none.
+    common_window: Option<CommonWindow>,
+}
+
+/// Whether `time` falls in `[start, end)`. A window whose end is before its
+/// start runs overnight.
+pub(super) fn in_window(time: NaiveTime, start: NaiveTime, end: NaiveTime) -> bool {
+    if start <= end {
+        start <= time && time < end
+    } else {
+        time >= start || time < end
+    }
+}
+
+/// Trims, dedupes and bounds a list of requested user IDs.
+pub(super) fn requested_ids(raw: &[String]) -> Result<Vec<String>, String> {
+    let mut ids: Vec<String> = Vec::new();
+    for id in raw.iter().map(|id| id.trim()).filter(|id| !id.is_empty()) {
+        if !ids.iter().any(|seen| seen == id) {
+            ids.push(id.to_string());
+        }
+    }
+    if ids.is_empty() {
+        return Err("At least one user ID is required".into());
+    }
+    if ids.len() > MAX_OVERLAP_IDS {
+        return Err(format!(
+            "At most {} user IDs may be requested",
+            MAX_OVERLAP_IDS
+        ));
+    }
+    Ok(ids)
+}
+
+/// Stored username, timezone name and parsed zone for each of `ids` that has
+/// one. Entries whose zone no longer parses are left out.
+pub(super) async fn load_zones(
+    state: &AppState,
+    ids: &[String],
+) -> AppResult<HashMap<String, (String, String, Tz)>> {
+    let records = retry_read(|| state.store.batch(ids)).await?;
+
+    let mut found = HashMap::new();
+    for record in records {
+        if let Ok(tz) = record.timezone.parse::<Tz>() {
+            found.insert(record.user_id, (record.username, record.timezone, tz));
+        }
+    }
+    Ok(found)
+}
+
+/// Tells which of the given users are currently inside a local working-hours
+/// window, and when the whole group next overlaps.
+pub async fn overlap(
+    State(state): State<AppState>,
+    Json(body): Json<OverlapBody>,
+) -> AppResult<impl IntoResponse> {
+    let ids = requested_ids(&body.ids).map_err(AppError::bad_request)?;
+
+    let parse = |value: &str| NaiveTime::parse_from_str(value.trim(), "%H:%M");
+    let (Ok(start), Ok(end)) = (parse(&body.start), parse(&body.end)) else {
+        return Err(AppError::bad_request(
+            "start and end must be 24-hour HH:MM times",
+        ));
+    };
+    if start == end {
+        return Err(AppError::bad_request("start and end must differ"));
+    }
+
+    let mut found = load_zones(&state, &ids).await?;
+
+    let now = Utc::now();
+    let mut users = Vec::new();
+    let mut zones = Vec::new();
+    let mut missing = Vec::new();
+    for id in ids {
+        let Some((username, timezone, tz)) = found.remove(&id) else {
+            missing.push(id);
+            continue;
+        };
+        let local = now.with_timezone(&tz);
+        users.push(UserOverlap {
+            id,
+            username,
+            timezone,
+            local_time: local.format("%H:%M").to_string(),
+            within: in_window(local.time(), start, end),
+        });
+        zones.push(tz);
+    }
+
+    let within_count = users.iter().filter(|user| user.within).count();
+    let common_window = common_window(&zones, now, start, end);
+
+    Ok(Json(OverlapResponse {
+        all_within: !users.is_empty() && within_count == users.len(),
+        within_count,
+        users,
+        missing,
+        common_window,
+    }))
+}
+
+/// Scans the next day minute by minute for the first run where every zone is
+/// inside the window. Sampling copes with overnight windows and DST shifts
+/// without special cases.
+fn common_window(
+    zones: &[Tz],
+    now: DateTime<Utc>,
+    start: NaiveTime,
+    end: NaiveTime,
+) -> Option<CommonWindow> {
+    if zones.is_empty() {
+        return None;
+    }
+    let from = now.duration_trunc(Duration::minutes(1)).unwrap_or(now);
+    let everyone_in = |at: DateTime<Utc>| {
+        zones
+            .iter()
+            .all(|tz| in_window(at.with_timezone(tz).time(), start, end))
+    };
+
+    let opens = (0..SEARCH_MINUTES)
+        .map(|m| from + Duration::minutes(m))
+        .find(|at| everyone_in(*at))?;
+    let closes = (1..=SEARCH_MINUTES)
+        .map(|m| opens + Duration::minutes(m))
+        .find(|at| !everyone_in(*at))
+        .unwrap_or(opens + Duration::minutes(SEARCH_MINUTES));
+
+    let format = |at: DateTime<Utc>| at.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    Some(CommonWindow {
+        start: format(opens),
+        end: format(closes),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(value, "%H:%M").unwrap()
+    }
+
+    fn window(zones: &[&str], now: &str, start: &str, end: &str) -> Option<(String, String)> {
+        let zones: Vec<Tz> = zones.iter().map(|name| name.parse().unwrap()).collect();
+        common_window(&zones, now.parse().unwrap(), time(start), time(end))
+            .map(|window| (window.start, window.end))
+    }
+
+    #[test]
+    fn daytime_window_includes_start_only() {
+        let (start, end) = (time("09:00"), time("17:00"));
+        assert!(in_window(time("09:00"), start, end));
+        assert!(in_window(time("16:59"), start, end));
+        assert!(!in_window(time("17:00"), start, end));
+        assert!(!in_window(time("08:59"), start, end));
+    }
+
+    #[test]
+    fn overnight_window_wraps_midnight() {
+        let (start, end) = (time("22:00"), time("06:00"));
+        assert!(in_window(time("22:00"), start, end));
+        assert!(in_window(time("23:59"), start, end));
+        assert!(in_window(time("00:00"), start, end));
+        assert!(in_window(time("05:59"), start, end));
+        assert!(!in_window(time("06:00"), start, end));
+        assert!(!in_window(time("12:00"), start, end));
+    }
+
+    #[test]
+    fn equal_bounds_are_empty() {
+        assert!(!in_window(time("09:00"), time("09:00"), time("09:00")));
+    }
+
+    #[test]
+    fn common_window_across_zones() {
+        assert_eq!(
+            window(
+                &["America/New_York", "Europe/London"],
+                "2025-06-02T00:00:00Z",
+                "09:00",
+                "17:00"
+            ),
+            Some((
+                "2025-06-02T13:00:00Z".to_string(),
+                "2025-06-02T16:00:00Z".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn common_window_already_open_starts_now() {
+        assert_eq!(
+            window(
+                &["America/New_York", "Europe/London"],
+                "2025-06-02T14:30:45Z",
+                "09:00",
+                "17:00"
+            ),
+            Some((
+                "2025-06-02T14:30:00Z".to_string(),
+                "2025-06-02T16:00:00Z".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn common_window_overnight() {
+        assert_eq!(
+            window(
+                &["Europe/London", "UTC"],
+                "2025-06-02T12:00:00Z",
+                "22:00",
+                "06:00"
+            ),
+            Some((
+                "2025-06-02T22:00:00Z".to_string(),
+                "2025-06-03T05:00:00Z".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn no_common_window() {
+        assert_eq!(
+            window(
+                &["Asia/Tokyo", "America/Los_Angeles"],
+                "2025-06-02T00:00:00Z",
+                "09:00",
+                "12:00"
+            ),
+            None
+        );
+        assert_eq!(window(&[], "2025-06-02T00:00:00Z", "09:00", "17:00"), None);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::db::{
+    redis_helper::{cache_get, cache_set, ttl_with_jitter},
+    store::retry_read,
+    AppState,
+};
+use crate::error::{AppError, AppResult};
+use crate::routes::info::load_profile;
+use crate::tz;
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 200;
+/// Seconds a `match=offset` result is reused for.
+const CACHE_TTL: u64 = 60;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Match {
+    /// The same stored IANA name.
+    #[default]
+    Zone,
+    /// Any zone whose clock currently reads the same.
+    Offset,
+}
+
+#[derive(Deserialize)]
+pub struct SameZoneQuery {
+    id: String,
+    #[serde(default, rename = "match")]
+    by: Match,
+    limit: Option<usize>,
+    /// Continue after this user ID, from a previous page's `next`.
+    after: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ZoneMate {
+    id: String,
+    username: String,
+    timezone: String,
+}
+
+#[derive(Serialize)]
+struct SameZoneResponse {
+    id: String,
+    timezone: String,
+    utc_offset_seconds: i32,
+    /// Matching users on every page together.
+    count: usize,
+    users: Vec<ZoneMate>,
+    /// The `after` for the next page; `None` on the last one.
+    next: Option<String>,
+}
+
+/// Other stored users in the same zone as `id`, or with `match=offset` in any
+/// zone at the same offset right now, ordered by user ID and paged with
+/// `limit` and `after`. Offset matches are cached for `CACHE_TTL` seconds.
+pub async fn same_zone(
+    State(state): State<AppState>,
+    Query(query): Query<SameZoneQuery>,
+) -> AppResult<impl IntoResponse> {
+    let id = query.id.trim();
+    if id.is_empty() {
+        return Err(AppError::bad_request("A user ID is required"));
+    }
+    let limit = match query.limit {
+        None => DEFAULT_LIMIT,
+        Some(limit) if (1..=MAX_LIMIT).contains(&limit) => limit,
+        Some(_) => {
+            return Err(AppError::bad_request(format!(
+                "limit must be between 1 and {}",
+                MAX_LIMIT
+            )))
+        }
+    };
+
+    let (profile, target) = load_profile(&state, id).await?;
+    let now = Utc::now();
+    let offset = tz::offset_at(target, now).utc_offset_seconds;
+    let after = query.after.as_deref().unwrap_or_default();
+
+    let (mut users, count) = match query.by {
+        Match::Zone => {
+            // One extra row tells whether there is another page.
+            let fetch = i64::try_from(limit + 1).unwrap_or(i64::MAX);
+            let (records, count) =
+                retry_read(|| state.store.same_zone(&profile.timezone, id, after, fetch)).await?;
+            let users: Vec<ZoneMate> = records
+                .into_iter()
+                .map(|record| ZoneMate {
+                    id: record.user_id,
+                    username: record.username,
+                    timezone: record.timezone,
+                })
+                .collect();
+            (users, usize::try_from(count).unwrap_or_default())
+        }
+        Match::Offset => {
+            let mut mates = users_at_offset(&state, offset).await?;
+            mates.retain(|mate| mate.id != id);
+            let count = mates.len();
+            mates.retain(|mate| mate.id.as_str() > after);
+            mates.truncate(limit + 1);
+            (mates, count)
+        }
+    };
+    let next = if users.len() > limit {
+        users.truncate(limit);
+        users.last().map(|user| user.id.clone())
+    } else {
+        None
+    };
+
+    Ok(Json(SameZoneResponse {
+        id: id.to_string(),
+        timezone: profile.timezone,
+        utc_offset_seconds: offset,
+        count,
+        users,
+        next,
+    }))
+}
+
+/// Every stored user whose zone is at `offset` right now, ordered by user ID.
+/// Reads the whole table, computing each distinct zone once, so results are
+/// cached for `CACHE_TTL` seconds.
+async fn users_at_offset(state: &AppState, offset: i32) -> AppResult<Vec<ZoneMate>> {
+    let key = format!("same-offset:{}", offset);
+    if let Some(cached) = cache_get::<Vec<ZoneMate>>(&state.kv, &key).await {
+        return Ok(cached);
+    }
+
+    let records = retry_read(|| state.store.list()).await?;
+    let now = Utc::now();
+    let mut offsets: HashMap<String, Option<i32>> = HashMap::new();
+    let mates: Vec<ZoneMate> = records
+        .into_iter()
+        .filter(|record| {
+            let theirs = offsets.entry(record.timezone.clone()).or_insert_with(|| {
+                let tz = record.timezone.parse::<Tz>().ok()?;
+                Some(tz::offset_at(tz, now).utc_offset_seconds)
+            });
+            *theirs == Some(offset)
+        })
+        .map(|record| ZoneMate {
+            id: record.user_id,
+            username: record.username,
+            timezone: record.timezone,
+        })
+        .collect();
+
+    let ttl = ttl_with_jitter(CACHE_TTL, state.config.ttl_jitter_pct);
+    cache_set(&state.kv, &key, &mates, ttl).await;
+    Ok(mates)
+}
@@ -1,282 +1,842 @@
-use crate::db::AppState;
-use crate::routes::auth::DiscordUser;
+use crate::config::Config;
+use crate::db::{
+    redis_helper::{cache_del, cache_get, cache_set, hit_rate_limit, RateLimitStatus},
+    store::{is_check_violation, retry_read, Coordinates, TimezoneWrite},
+    AppState,
+};
+use crate::error::{AppError, AppResult};
+use crate::middleware::api_key::{require_scope, ApiKey, ApiScope};
+use crate::middleware::field_case::to_json;
+use crate::routes::auth::{avatar_url, get_user_from_session, DiscordUser};
+use crate::routes::{comma_separated, info::info_cache_key, optional_user_id};
 use crate::types::JsonMessage;
+use crate::tz::{self, CityZone, Formats, LocationMatch, RegionHint, TimeFormat};
+use crate::webhook::{self, TimezoneAction, TimezoneEvent};
 use axum::{
-    extract::{Query, State},
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
-    Json,
+    body::{Body, Bytes},
+    extract::{FromRequest, Query, Request, State},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE, WARNING},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Extension, Form, Json,
 };
+use chrono::{Datelike, Utc};
 use chrono_tz::Tz;
-use headers::{Cookie, HeaderMapExt};
-use redis::AsyncCommands;
-use serde::{Deserialize, Serialize};
-use sqlx::Row;
+use futures_util::{stream, StreamExt};
+use headers::{HeaderMapExt, IfModifiedSince, LastModified};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::{sync::mpsc, time::Instant};
+use tracing::{error, info, warn};
 
 #[derive(Serialize)]
 pub struct TimezoneResponse {
     user: UserInfo,
     timezone: String,
+    observes_dst: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<RegionHint>,
+    /// POSIX `TZ` string, with `format=posix`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    posix: Option<String>,
+    /// The current local time in every format, with `include=formats`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    formats: Option<Formats>,
+    /// Localized display name, with `names=localized`. Falls back to the
+    /// IANA name for zones or languages without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct MinimalUserInfo {
     username: String,
     timezone: String,
 }
 
+/// One line of the `application/x-ndjson` list.
+#[derive(Serialize)]
+struct ListLine<'a> {
+    user_id: &'a str,
+    username: &'a str,
+    timezone: &'a str,
+}
+
 #[derive(Serialize)]
 pub struct UserInfo {
     id: String,
     username: String,
+    avatar_url: String,
 }
 
+const NDJSON: &str = "application/x-ndjson";
+
+/// Where the last list served is kept for `LIST_STALE_ON_ERROR`.
+const STALE_LIST_KEY: &str = "list:last";
+
+/// How long that copy is kept; it only has to outlast a database outage.
+const STALE_LIST_TTL: u64 = 24 * 60 * 60;
+
+/// The `last_modified` of the kept copy, so it is only rewritten when the
+/// table has changed.
+const STALE_LIST_VERSION_KEY: &str = "list:last:modified";
+
+/// How long that version is trusted. `last_modified` has whole-second
+/// resolution, so a write in the same second as the copy would otherwise go
+/// unnoticed until the next one.
+const STALE_LIST_REFRESH: u64 = 5 * 60;
+
+/// Streamed list lines are sent once this many bytes are waiting...
+const LIST_CHUNK_LEN: usize = 16 * 1024;
+
+/// ...or once the oldest waiting line is this old, so a slow query still
+/// reaches the client steadily.
+const LIST_FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Chunks buffered ahead of a slow client before the query waits for it.
+const LIST_BUFFERED_CHUNKS: usize = 4;
+
+/// Upper bound on ids accepted by a single `/get` call.
+const MAX_GET_IDS: usize = 100;
+
 #[derive(Deserialize)]
 pub struct GetQuery {
-    id: String,
+    #[serde(deserialize_with = "comma_separated")]
+    id: Vec<String>,
+    #[serde(default)]
+    multi: bool,
+    #[serde(default, deserialize_with = "comma_separated")]
+    include: Vec<String>,
+    /// `posix` adds the POSIX `TZ` string next to the IANA name.
+    format: Option<String>,
+    /// `localized` adds `display_name` in `locale`.
+    names: Option<String>,
+    locale: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct SetQuery {
     timezone: String,
+    #[serde(default, deserialize_with = "optional_user_id")]
+    user_id: Option<String>,
+    username: Option<String>,
+    /// Preferred `TimeFormat` preset; left unchanged when omitted and cleared
+    /// when empty.
+    time_format: Option<String>,
+    /// Optional location, given together; left unchanged when omitted and
+    /// cleared when both are empty. Only ever returned to the user themselves.
+    #[serde(default, deserialize_with = "clearable_coordinate")]
+    lat: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "clearable_coordinate")]
+    lon: Option<Option<f64>>,
 }
 
+/// A coordinate as clients send it: a JSON number, or text from a form or
+/// query string. JSON `null` and empty text both mean "clear it".
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a number, or an empty value to clear it")]
+enum RawCoordinate {
+    Number(f64),
+    Text(String),
+}
+
+/// `Some(None)` for an empty coordinate; pair with `#[serde(default)]` so an
+/// omitted one stays `None`.
+fn clearable_coordinate<'de, D>(deserializer: D) -> Result<Option<Option<f64>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<RawCoordinate>::deserialize(deserializer)? {
+        None => Ok(Some(None)),
+        Some(RawCoordinate::Number(value)) => Ok(Some(Some(value))),
+        Some(RawCoordinate::Text(text)) if text.trim().is_empty() => Ok(Some(None)),
+        Some(RawCoordinate::Text(text)) => text
+            .trim()
+            .parse()
+            .map(|value| Some(Some(value)))
+            .map_err(|_| D::Error::custom("expected a number, or an empty value to clear it")),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SetByLocationBody {
+    /// ISO 3166 two-letter code.
+    country: String,
+    city: String,
+    #[serde(default, deserialize_with = "optional_user_id")]
+    user_id: Option<String>,
+    username: Option<String>,
+    time_format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SetResponse {
+    message: &'static str,
+    /// Whether this write created the user's entry rather than replacing it.
+    created: bool,
+}
+
+#[derive(Serialize)]
+struct LocationMiss {
+    message: &'static str,
+    candidates: Vec<CityZone>,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteQuery {
+    #[serde(default, deserialize_with = "optional_user_id")]
+    user_id: Option<String>,
+}
+
+/// Looks up one or more comma-separated ids. A single id keeps the original
+/// single-object response (404 when missing); several ids, or `multi=true`,
+/// return an array of the users that were found, in request order.
 pub async fn get_timezone(
     State(state): State<AppState>,
     Query(query): Query<GetQuery>,
-) -> impl IntoResponse {
-    let row = sqlx::query("SELECT username, timezone FROM timezones WHERE user_id = $1")
-        .bind(&query.id)
-        .fetch_optional(&state.db)
-        .await;
+) -> AppResult<Response> {
+    if query.id.is_empty() {
+        return Err(AppError::bad_request("At least one user ID is required"));
+    }
+
+    if query.id.len() > MAX_GET_IDS {
+        return Err(AppError::bad_request(format!(
+            "At most {} user IDs may be requested",
+            MAX_GET_IDS
+        )));
+    }
+
+    let include_posix = match query.format.as_deref() {
+        None => false,
+        Some("posix") => true,
+        Some(_) => return Err(AppError::bad_request("Unknown format; expected posix")),
+    };
 
-    match row {
-        Ok(Some(record)) => {
+    let include_names = match query.names.as_deref() {
+        None => false,
+        Some("localized") => true,
+        Some(_) => return Err(AppError::bad_request("Unknown names; expected localized")),
+    };
+    let locale = query.locale.as_deref().unwrap_or("en");
+
+    let records = retry_read(|| state.store.batch(&query.id)).await?;
+
+    let include_flag = query.include.iter().any(|part| part == "flag");
+    let include_formats = query.include.iter().any(|part| part == "formats");
+    let now = Utc::now();
+    let year = now.year();
+    let mut found: HashMap<String, TimezoneResponse> = records
+        .into_iter()
+        .map(|record| {
+            let id = record.user_id;
+            let timezone = record.timezone;
+            let parsed = timezone.parse::<Tz>().ok();
+            let region = include_flag
+                .then(|| parsed.and_then(tz::region_hint))
+                .flatten();
             let response = TimezoneResponse {
                 user: UserInfo {
-                    id: query.id,
-                    username: record.get("username"),
+                    avatar_url: avatar_url(&id, record.avatar.as_deref()),
+                    id: id.clone(),
+                    username: record.username,
                 },
-                timezone: record.get("timezone"),
+                observes_dst: parsed.is_some_and(|tz| tz::observes_dst(tz, year)),
+                region,
+                posix: include_posix
+                    .then(|| parsed.map(|tz| tz::posix_tz(tz, now)))
+                    .flatten(),
+                formats: include_formats
+                    .then(|| parsed.map(|tz| tz::formats(&now.with_timezone(&tz))))
+                    .flatten(),
+                display_name: include_names.then(|| {
+                    parsed
+                        .and_then(|tz| tz::localized_name(tz, locale))
+                        .unwrap_or_else(|| timezone.clone())
+                }),
+                timezone,
             };
-            (StatusCode::OK, Json(response)).into_response()
+            (id, response)
+        })
+        .collect();
+
+    if query.id.len() == 1 && !query.multi {
+        let response = found
+            .remove(&query.id[0])
+            .ok_or_else(|| AppError::not_found("User not found"))?;
+        return Ok(Json(response).into_response());
+    }
+
+    let responses: Vec<TimezoneResponse> =
+        query.id.iter().filter_map(|id| found.remove(id)).collect();
+    Ok(Json(responses).into_response())
+}
+
+/// The full list, with `Last-Modified` set from the latest write or deletion
+/// so polling clients can send `If-Modified-Since` and get a bodyless 304
+/// while nothing has changed. With `Accept: application/x-ndjson` the rows
+/// are streamed one object per line instead of collected into one object.
+pub async fn list_timezones(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let wants_ndjson = headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains(NDJSON));
+    let modified = match retry_read(|| state.store.last_modified()).await {
+        Ok(secs) => secs,
+        Err(err) if !wants_ndjson => return stale_list(&state, err).await,
+        Err(err) => return Err(AppError::db("Failed to fetch list")(err)),
+    };
+    let last_modified = modified.map(|secs| UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64));
+
+    let mut response_headers = HeaderMap::new();
+    if let Some(at) = last_modified {
+        response_headers.typed_insert(LastModified::from(at));
+        let unchanged = headers
+            .typed_get::<IfModifiedSince>()
+            .is_some_and(|since| !since.is_modified(at));
+        if unchanged {
+            return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
         }
-        Ok(None) => (
-            StatusCode::NOT_FOUND,
-            Json(JsonMessage {
-                message: "User not found".into(),
-            }),
-        )
-            .into_response(),
-        Err(_) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(JsonMessage {
-                message: "Database error".into(),
-            }),
-        )
-            .into_response(),
     }
+
+    if wants_ndjson {
+        response_headers.insert(CONTENT_TYPE, HeaderValue::from_static(NDJSON));
+        return Ok((response_headers, stream_list(&state)).into_response());
+    }
+
+    let records = match retry_read(|| state.store.list()).await {
+        Ok(records) => records,
+        Err(err) => return stale_list(&state, err).await,
+    };
+    let mut result = HashMap::new();
+    for record in records {
+        result.insert(
+            record.user_id,
+            MinimalUserInfo {
+                username: record.username,
+                timezone: record.timezone,
+            },
+        );
+    }
+    if state.config.list_stale_on_error {
+        keep_stale_list(&state, modified, &result).await;
+    }
+    Ok((response_headers, Json(result)).into_response())
 }
 
-pub async fn list_timezones(State(state): State<AppState>) -> impl IntoResponse {
-    let rows = sqlx::query("SELECT user_id, username, timezone FROM timezones")
-        .fetch_all(&state.db)
-        .await;
+/// Saves `list` for `stale_list`, unless the copy already kept was read at
+/// the same `modified` within the last `STALE_LIST_REFRESH` seconds. Busy
+/// dashboards poll constantly; rewriting the whole table into Redis on every
+/// poll would cost more than the outage it guards against.
+async fn keep_stale_list(
+    state: &AppState,
+    modified: Option<i64>,
+    list: &HashMap<String, MinimalUserInfo>,
+) {
+    let version = modified.unwrap_or_default();
+    if cache_get::<i64>(&state.kv, STALE_LIST_VERSION_KEY).await == Some(version) {
+        return;
+    }
+    cache_set(&state.kv, STALE_LIST_KEY, list, STALE_LIST_TTL).await;
+    cache_set(
+        &state.kv,
+        STALE_LIST_VERSION_KEY,
+        &version,
+        STALE_LIST_REFRESH,
+    )
+    .await;
+}
+
+/// What `/list` answers when the database fails. Normally the error itself;
+/// with `LIST_STALE_ON_ERROR` the last list served, or an empty one if none
+/// was kept, marked with a `Warning` header so dashboards keep rendering
+/// through a brief outage.
+async fn stale_list(state: &AppState, err: sqlx::Error) -> AppResult<Response> {
+    let err = AppError::db("Failed to fetch list")(err);
+    if !state.config.list_stale_on_error {
+        return Err(err);
+    }
+    if let AppError::Db { message, source } = &err {
+        warn!("{}, serving the last copy: {}", message, source);
+    }
+    let list = cache_get::<HashMap<String, MinimalUserInfo>>(&state.kv, STALE_LIST_KEY)
+        .await
+        .unwrap_or_default();
+    Ok((
+        [(
+            WARNING,
+            HeaderValue::from_static("110 - \"Response is Stale\""),
+        )],
+        Json(list),
+    )
+        .into_response())
+}
 
-    match rows {
-        Ok(data) => {
-            let mut result = HashMap::new();
-            for r in data {
-                result.insert(
-                    r.get::<String, _>("user_id"),
-                    MinimalUserInfo {
-                        username: r.get("username"),
-                        timezone: r.get("timezone"),
-                    },
-                );
+/// Streams every entry as JSON Lines from a database cursor, like the CSV
+/// export. A database error partway through aborts the response, so the
+/// client sees a truncated stream rather than one that looks complete.
+fn stream_list(state: &AppState) -> Body {
+    let store = state.store.clone();
+    let case = state.config.api_field_case;
+    let (tx, rx) = mpsc::channel::<Result<Bytes, io::Error>>(LIST_BUFFERED_CHUNKS);
+    tokio::spawn(async move {
+        let mut rows = store.export();
+        let mut chunk = String::new();
+        let mut deadline = Instant::now() + LIST_FLUSH_INTERVAL;
+        loop {
+            let row = match tokio::time::timeout_at(deadline, rows.next()).await {
+                Ok(Some(Ok(row))) => Some(row),
+                Ok(Some(Err(err))) => {
+                    error!("Streaming list failed: {}", err);
+                    tx.send(Err(io::Error::other("list failed"))).await.ok();
+                    return;
+                }
+                Ok(None) => break,
+                Err(_) => None,
+            };
+            if let Some(row) = row {
+                let record = &row.record;
+                let line = ListLine {
+                    user_id: &record.user_id,
+                    username: &record.username,
+                    timezone: &record.timezone,
+                };
+                if let Ok(json) = to_json(&line, case) {
+                    chunk.push_str(&json);
+                    chunk.push('\n');
+                }
+            }
+            let due = Instant::now() >= deadline;
+            if chunk.len() >= LIST_CHUNK_LEN || (due && !chunk.is_empty()) {
+                let full = std::mem::take(&mut chunk);
+                if tx.send(Ok(Bytes::from(full))).await.is_err() {
+                    // The client went away.
+                    return;
+                }
             }
-            (StatusCode::OK, Json(result)).into_response()
+            if due || chunk.is_empty() {
+                deadline = Instant::now() + LIST_FLUSH_INTERVAL;
+            }
+        }
+        if !chunk.is_empty() {
+            tx.send(Ok(Bytes::from(chunk))).await.ok();
         }
-        Err(_) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(JsonMessage {
-                message: "Failed to fetch list".into(),
-            }),
-        )
-            .into_response(),
+    });
+
+    Body::from_stream(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }))
+}
+
+/// Checks user input against the IANA database and the operator's policy. Every
+/// write path goes through here so sessions and API keys get the same rules.
+pub(super) fn validate_timezone(input: &str, config: &Config) -> Result<Tz, &'static str> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("Timezone is required");
+    }
+
+    if input.len() > tz::MAX_NAME_LEN {
+        return Err("Timezone is too long");
+    }
+
+    let valid_chars = input
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'));
+    if !valid_chars {
+        return Err("Timezone contains invalid characters");
     }
+
+    // `america/new_york` is unambiguous; store it under its proper spelling.
+    let tz = input
+        .parse::<Tz>()
+        .ok()
+        .or_else(|| tz::find_case_insensitive(input))
+        .ok_or("Invalid timezone")?;
+
+    config.allows_zone(tz)?;
+    Ok(tz)
 }
 
-pub async fn delete_timezone(
+/// Upper bound on entries accepted by a single `/validate/batch` call.
+const MAX_VALIDATE_BATCH: usize = 100;
+
+/// Largest `/validate/batch` body: room for a full batch of maximum-length
+/// names, with plenty to spare for escaping.
+pub(super) const MAX_VALIDATE_BODY: usize = 16 * 1024;
+
+#[derive(Deserialize)]
+pub struct ValidateBatchBody {
+    timezones: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ValidationResult {
+    input: String,
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    canonical: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+    suggestions: Vec<&'static str>,
+}
+
+/// Dry-runs `validate_timezone` over a batch so import tooling can check its
+/// data first. Writes nothing and needs no auth.
+pub async fn validate_batch(
     State(state): State<AppState>,
-    headers: HeaderMap,
-) -> impl IntoResponse {
-    let Some(cookie_header) = headers.typed_get::<Cookie>() else {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Missing session cookie".into(),
-            }),
-        )
-            .into_response();
-    };
+    Json(body): Json<ValidateBatchBody>,
+) -> AppResult<impl IntoResponse> {
+    if body.timezones.len() > MAX_VALIDATE_BATCH {
+        return Err(AppError::bad_request(format!(
+            "At most {} timezones may be validated at once",
+            MAX_VALIDATE_BATCH
+        )));
+    }
 
-    let Some(session_id) = cookie_header.get("session") else {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Missing session ID".into(),
-            }),
-        )
-            .into_response();
-    };
+    let results: Vec<ValidationResult> = body
+        .timezones
+        .into_iter()
+        .map(|input| match validate_timezone(&input, &state.config) {
+            Ok(tz) => ValidationResult {
+                input,
+                valid: true,
+                canonical: Some(tz::canonical(tz).name()),
+                error: None,
+                suggestions: Vec::new(),
+            },
+            Err(error) => ValidationResult {
+                suggestions: tz::suggest(&input, 5),
+                input,
+                valid: false,
+                canonical: None,
+                error: Some(error),
+            },
+        })
+        .collect();
 
-    let mut redis = state.redis.clone();
-    let key = format!("session:{}", session_id);
-    let json: redis::RedisResult<String> = redis.get(&key).await;
-
-    let Ok(json) = json else {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Session not found".into(),
-            }),
-        )
-            .into_response();
-    };
+    Ok(Json(results))
+}
 
-    let Ok(user) = serde_json::from_str::<DiscordUser>(&json) else {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Invalid user session".into(),
-            }),
-        )
-            .into_response();
+/// Resolves the user a write request acts on. Browser callers act on the user
+/// behind their session; API keys with write scope act on the `user_id` they
+/// name instead.
+async fn resolve_target_user(
+    headers: &HeaderMap,
+    state: &AppState,
+    api_key: Option<&ApiKey>,
+    user_id: Option<&str>,
+) -> AppResult<DiscordUser> {
+    let Some(user_id) = user_id else {
+        return get_user_from_session(headers, state).await;
     };
 
-    let result = sqlx::query("DELETE FROM timezones WHERE user_id = $1")
-        .bind(&user.id)
-        .execute(&state.db)
-        .await;
+    let key = require_scope(api_key, ApiScope::Write)?;
 
-    match result {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(JsonMessage {
-                message: "Timezone deleted".into(),
-            }),
-        )
-            .into_response(),
-        Err(_) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(JsonMessage {
-                message: "Delete failed".into(),
-            }),
-        )
-            .into_response(),
+    let user_id = user_id.trim();
+    if user_id.is_empty() {
+        return Err(AppError::bad_request("User ID is required"));
     }
+
+    info!(
+        "API key {} ({}) writing on behalf of {}",
+        key.name, key.id, user_id
+    );
+
+    Ok(DiscordUser {
+        id: user_id.to_string(),
+        username: String::new(),
+        discriminator: "0".into(),
+        avatar: None,
+    })
+}
+
+pub async fn delete_timezone(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    api_key: Option<Extension<ApiKey>>,
+    Query(query): Query<DeleteQuery>,
+) -> AppResult<Json<JsonMessage>> {
+    let user = resolve_target_user(
+        &headers,
+        &state,
+        api_key.as_deref(),
+        query.user_id.as_deref(),
+    )
+    .await?;
+
+    let deleted = state
+        .store
+        .delete(&user.id)
+        .await
+        .map_err(AppError::db("Delete failed"))?;
+    if deleted.is_none() && state.config.delete_returns_404_when_missing {
+        return Err(AppError::not_found("Nothing to delete"));
+    }
+
+    cache_del(&state.kv, &info_cache_key(&user.id)).await;
+    if let Some(old) = deleted {
+        let event = TimezoneEvent::new(&user.id, Some(old), None, TimezoneAction::Delete);
+        state.events.publish(&event);
+        webhook::dispatch(&state.http, state.config.webhook.as_ref(), event);
+    }
+    Ok(Json(JsonMessage {
+        message: "Timezone deleted".into(),
+    }))
 }
 
 pub async fn set_timezone(
     State(state): State<AppState>,
     headers: HeaderMap,
+    api_key: Option<Extension<ApiKey>>,
     Query(query): Query<SetQuery>,
-) -> impl IntoResponse {
-    let Some(cookie_header) = headers.typed_get::<Cookie>() else {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Missing session cookie".into(),
-            }),
-        )
-            .into_response();
-    };
+) -> AppResult<Response> {
+    save_timezone(&state, &headers, api_key.as_deref(), query).await
+}
 
-    let Some(session_id) = cookie_header.get("session") else {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Missing session ID".into(),
-            }),
-        )
-            .into_response();
-    };
+/// `POST /set` with the same fields as the query string, sent either as a
+/// form or as JSON. Anything else is a 415 rather than axum's plain-text
+/// extractor rejection.
+pub async fn set_timezone_body(
+    State(state): State<AppState>,
+    api_key: Option<Extension<ApiKey>>,
+    req: Request,
+) -> AppResult<Response> {
+    let headers = req.headers().clone();
+    let mime = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .unwrap_or_default();
 
-    let mut redis = state.redis.clone();
-    let key = format!("session:{}", session_id);
-    let json: redis::RedisResult<String> = redis.get(&key).await;
-
-    let Ok(json) = json else {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Session not found".into(),
-            }),
-        )
-            .into_response();
+    let query = match mime.as_str() {
+        "application/x-www-form-urlencoded" => {
+            let Form(query) = Form::<SetQuery>::from_request(req, &state).await?;
+            query
+        }
+        "application/json" => {
+            let Json(query) = Json::<SetQuery>::from_request(req, &state).await?;
+            query
+        }
+        _ => {
+            return Err(AppError::UnsupportedMediaType(
+                "Expected application/x-www-form-urlencoded or application/json".into(),
+            ))
+        }
     };
 
-    let Ok(user) = serde_json::from_str::<DiscordUser>(&json) else {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(JsonMessage {
-                message: "Invalid user session".into(),
-            }),
-        )
-            .into_response();
+    save_timezone(&state, &headers, api_key.as_deref(), query).await
+}
+
+/// `POST /set-by-location` for front-ends that ask for a country and city
+/// rather than a zone name. The pair is resolved with
+/// `tz::find_by_location` and then saved exactly like `/set`.
+pub async fn set_by_location(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    api_key: Option<Extension<ApiKey>>,
+    Json(body): Json<SetByLocationBody>,
+) -> AppResult<Response> {
+    let (message, candidates) = match tz::find_by_location(&body.country, &body.city) {
+        LocationMatch::Found(tz) => {
+            let query = SetQuery {
+                timezone: tz.name().to_string(),
+                user_id: body.user_id,
+                username: body.username,
+                time_format: body.time_format,
+                lat: None,
+                lon: None,
+            };
+            return save_timezone(&state, &headers, api_key.as_deref(), query).await;
+        }
+        LocationMatch::UnknownCountry => {
+            return Err(AppError::bad_request("Unknown country code"));
+        }
+        LocationMatch::Ambiguous(candidates) => ("City is in more than one timezone", candidates),
+        LocationMatch::NotFound(candidates) => ("City not found", candidates),
     };
+    Ok((
+        StatusCode::BAD_REQUEST,
+        Json(LocationMiss {
+            message,
+            candidates,
+        }),
+    )
+        .into_response())
+}
+
+async fn save_timezone(
+    state: &AppState,
+    headers: &HeaderMap,
+    api_key: Option<&ApiKey>,
+    query: SetQuery,
+) -> AppResult<Response> {
+    let mut user = resolve_target_user(headers, state, api_key, query.user_id.as_deref()).await?;
 
-    let tz_input = query.timezone.trim();
-    if tz_input.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(JsonMessage {
-                message: "Timezone is required".into(),
-            }),
-        )
-            .into_response();
+    if query.user_id.is_some() {
+        let username = query
+            .username
+            .as_deref()
+            .map(str::trim)
+            .filter(|u| !u.is_empty())
+            .ok_or_else(|| AppError::bad_request("Username is required"))?;
+        user.username = username.to_string();
     }
 
-    if tz_input.parse::<Tz>().is_err() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(JsonMessage {
-                message: "Invalid timezone".into(),
-            }),
-        )
-            .into_response();
+    // Checked before the limiter, so a rejected request doesn't use up a write.
+    let input = validate_write(&query, &state.config)?;
+
+    if query.user_id.is_none() && state.config.write_rate.limit > 0 {
+        let limit = state.config.write_rate.limit;
+        let key = format!("writerate:{}", user.id);
+        match hit_rate_limit(&state.kv, &key, state.config.write_rate.window).await {
+            // The 429 carries the headers too, so both outcomes are built
+            // into a response here.
+            Ok(status) => {
+                let mut res = if status.count > limit {
+                    AppError::TooManyRequests("Too many timezone changes, try again later".into())
+                        .into_response()
+                } else {
+                    store_timezone(state, &user, &query, input)
+                        .await
+                        .into_response()
+                };
+                insert_rate_limit_headers(res.headers_mut(), limit, &status);
+                return Ok(res);
+            }
+            // The limiter is a safeguard, not a dependency: fail open.
+            Err(err) => warn!("Skipping write rate limit, Redis unavailable: {}", err),
+        }
     }
 
-    let result = sqlx::query(
-        r#"
-			INSERT INTO timezones (user_id, username, timezone)
-			VALUES ($1, $2, $3)
-			ON CONFLICT (user_id) DO UPDATE
-			SET username = EXCLUDED.username, timezone = EXCLUDED.timezone
-		"#,
-    )
-    .bind(&user.id)
-    .bind(&user.username)
-    .bind(tz_input)
-    .execute(&state.db)
-    .await;
+    store_timezone(state, &user, &query, input).await
+}
 
-    match result {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(JsonMessage {
-                message: "Timezone saved".into(),
-            }),
-        )
-            .into_response(),
-        Err(_) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(JsonMessage {
-                message: "Database error".into(),
-            }),
-        )
-            .into_response(),
+/// Lets well-behaved clients pace themselves. `X-RateLimit-Reset` is the number
+/// of seconds until the current window ends.
+fn insert_rate_limit_headers(headers: &mut HeaderMap, limit: u64, status: &RateLimitStatus) {
+    headers.insert("x-ratelimit-limit", HeaderValue::from(limit));
+    headers.insert(
+        "x-ratelimit-remaining",
+        HeaderValue::from(limit.saturating_sub(status.count)),
+    );
+    headers.insert("x-ratelimit-reset", HeaderValue::from(status.reset_in));
+}
+
+/// `None` leaves the stored location alone and `Some(None)` clears it.
+fn coordinates(
+    lat: Option<Option<f64>>,
+    lon: Option<Option<f64>>,
+) -> Result<Option<Option<Coordinates>>, &'static str> {
+    match (lat, lon) {
+        (None, None) => Ok(None),
+        (Some(None), Some(None)) => Ok(Some(None)),
+        (Some(Some(lat)), Some(Some(lon))) => {
+            if !(-90.0..=90.0).contains(&lat) {
+                return Err("lat must be between -90 and 90");
+            }
+            if !(-180.0..=180.0).contains(&lon) {
+                return Err("lon must be between -180 and 180");
+            }
+            Ok(Some(Some(Coordinates { lat, lon })))
+        }
+        _ => Err("lat and lon must be given together"),
     }
 }
+
+/// The parts of a `/set` request that are checked before anything is written.
+struct WriteInput {
+    tz: Tz,
+    time_format: Option<Option<&'static str>>,
+    coordinates: Option<Option<Coordinates>>,
+}
+
+fn validate_write(query: &SetQuery, config: &Config) -> AppResult<WriteInput> {
+    let tz = validate_timezone(&query.timezone, config).map_err(AppError::bad_request)?;
+
+    let time_format = match query.time_format.as_deref().map(str::trim) {
+        None => None,
+        Some("") => Some(None),
+        Some(name) => Some(Some(
+            name.parse::<TimeFormat>()
+                .map_err(AppError::bad_request)?
+                .as_str(),
+        )),
+    };
+
+    let coordinates = coordinates(query.lat, query.lon).map_err(AppError::bad_request)?;
+
+    Ok(WriteInput {
+        tz,
+        time_format,
+        coordinates,
+    })
+}
+
+async fn store_timezone(
+    state: &AppState,
+    user: &DiscordUser,
+    query: &SetQuery,
+    input: WriteInput,
+) -> AppResult<Response> {
+    let WriteInput {
+        tz,
+        time_format,
+        coordinates,
+    } = input;
+
+    let result = state
+        .store
+        .set(TimezoneWrite {
+            user_id: &user.id,
+            username: &user.username,
+            timezone: tz.name(),
+            avatar: user.avatar.as_deref(),
+            replace_avatar: query.user_id.is_none(),
+            time_format,
+            coordinates,
+        })
+        .await;
+
+    let old = match result {
+        Ok(old) => old,
+        Err(err) if is_check_violation(&err) => {
+            return Err(AppError::bad_request(
+                "Timezone rejected by database constraints",
+            ));
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    cache_del(&state.kv, &info_cache_key(&user.id)).await;
+    let created = old.is_none();
+    let event = TimezoneEvent::new(
+        &user.id,
+        old,
+        Some(tz.name().to_string()),
+        TimezoneAction::Set,
+    );
+    state.events.publish(&event);
+    webhook::dispatch(&state.http, state.config.webhook.as_ref(), event);
+
+    let status = if created && state.config.set_returns_201_when_created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((
+        status,
+        Json(SetResponse {
+            message: "Timezone saved",
+            created,
+        }),
+    )
+        .into_response())
+}
@@ -0,0 +1,58 @@
+use crate::db::AppState;
+use crate::middleware::msgpack::wants_msgpack;
+use axum::{
+    extract::State,
+    http::{
+        header::{CACHE_CONTROL, VARY},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono_tz::{IANA_TZDB_VERSION, TZ_VARIANTS};
+use headers::{ETag, HeaderMapExt, IfNoneMatch};
+
+/// Changes exactly when the bundled tz database does, which only happens when
+/// `chrono_tz` is upgraded. The MessagePack body differs byte for byte, so it
+/// gets its own tag.
+fn etag(msgpack: bool) -> ETag {
+    let suffix = if msgpack { "-msgpack" } else { "" };
+    format!(
+        "\"tzdb-{}-{}{}\"",
+        IANA_TZDB_VERSION,
+        TZ_VARIANTS.len(),
+        suffix
+    )
+    .parse()
+    .expect("tzdb ETag is a valid header")
+}
+
+/// Every IANA zone name this build knows, sorted, for clients filling a
+/// picker. Served with a strong `ETag` and a long `Cache-Control`, so a client
+/// fetches it once per server upgrade.
+pub async fn list_zones(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let etag = etag(wants_msgpack(&headers));
+    let mut response_headers = HeaderMap::new();
+    response_headers.typed_insert(etag.clone());
+    response_headers.insert(VARY, HeaderValue::from_static("Accept"));
+    let max_age = state.config.timezones_max_age;
+    let cache_control = if max_age > 0 {
+        format!("public, max-age={}, immutable", max_age)
+    } else {
+        "no-cache".into()
+    };
+    if let Ok(value) = HeaderValue::from_str(&cache_control) {
+        response_headers.insert(CACHE_CONTROL, value);
+    }
+
+    let unchanged = headers
+        .typed_get::<IfNoneMatch>()
+        .is_some_and(|tags| !tags.precondition_passes(&etag));
+    if unchanged {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+
+    let mut names: Vec<&str> = TZ_VARIANTS.iter().map(|tz| tz.name()).collect();
+    names.sort_unstable();
+    (StatusCode::OK, response_headers, Json(names)).into_response()
+}
@@ -0,0 +1,51 @@
+// Hand-picked large cities that zone.tab doesn't name, in countries with more
+// than one zone. Cities that are a zone's own name (New York, Toronto) are
+// found from `ZONE_COUNTRIES` and don't need an entry here.
+
+/// `(ISO 3166 country code, city, zone)` triples sorted by country and city.
+pub static CITIES: &[(&str, &str, &str)] = &[
+    ("AU", "Canberra", "Australia/Sydney"),
+    ("BR", "Brasilia", "America/Sao_Paulo"),
+    ("BR", "Rio de Janeiro", "America/Sao_Paulo"),
+    ("CA", "Calgary", "America/Edmonton"),
+    ("CA", "Montreal", "America/Toronto"),
+    ("CA", "Ottawa", "America/Toronto"),
+    ("CA", "Quebec City", "America/Toronto"),
+    ("CN", "Beijing", "Asia/Shanghai"),
+    ("CN", "Guangzhou", "Asia/Shanghai"),
+    ("CN", "Shenzhen", "Asia/Shanghai"),
+    ("DE", "Cologne", "Europe/Berlin"),
+    ("DE", "Frankfurt", "Europe/Berlin"),
+    ("DE", "Hamburg", "Europe/Berlin"),
+    ("DE", "Munich", "Europe/Berlin"),
+    ("ES", "Barcelona", "Europe/Madrid"),
+    ("ES", "Seville", "Europe/Madrid"),
+    ("ES", "Valencia", "Europe/Madrid"),
+    ("ID", "Bali", "Asia/Makassar"),
+    ("ID", "Denpasar", "Asia/Makassar"),
+    ("ID", "Surabaya", "Asia/Jakarta"),
+    ("MX", "Guadalajara", "America/Mexico_City"),
+    ("NZ", "Christchurch", "Pacific/Auckland"),
+    ("NZ", "Wellington", "Pacific/Auckland"),
+    ("PT", "Porto", "Europe/Lisbon"),
+    ("RU", "Saint Petersburg", "Europe/Moscow"),
+    ("RU", "St Petersburg", "Europe/Moscow"),
+    ("US", "Atlanta", "America/New_York"),
+    ("US", "Austin", "America/Chicago"),
+    ("US", "Boston", "America/New_York"),
+    ("US", "Dallas", "America/Chicago"),
+    ("US", "Houston", "America/Chicago"),
+    ("US", "Las Vegas", "America/Los_Angeles"),
+    ("US", "Miami", "America/New_York"),
+    ("US", "Minneapolis", "America/Chicago"),
+    ("US", "Orlando", "America/New_York"),
+    ("US", "Philadelphia", "America/New_York"),
+    ("US", "Portland", "America/Los_Angeles"),
+    ("US", "Portland", "America/New_York"),
+    ("US", "Salt Lake City", "America/Denver"),
+    ("US", "San Diego", "America/Los_Angeles"),
+    ("US", "San Francisco", "America/Los_Angeles"),
+    ("US", "San Jose", "America/Los_Angeles"),
+    ("US", "Seattle", "America/Los_Angeles"),
+    ("US", "Washington", "America/New_York"),
+];
@@ -0,0 +1,744 @@
+//! Timezone facts derived from chrono-tz: canonical names, offsets, DST and
+//! upcoming transitions.
+
+mod cities;
+mod countries;
+mod links;
+mod names;
+
+use chrono::{
+    DateTime, Datelike, NaiveDate, NaiveDateTime, Offset, SecondsFormat, TimeZone, Timelike, Utc,
+};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+/// How far ahead `next_transition` looks before giving up.
+const TRANSITION_HORIZON_DAYS: i64 = 400;
+const DAY: i64 = 24 * 60 * 60;
+
+/// Resolves a backward-compatibility link (e.g. `US/Eastern`) to the zone it
+/// points at. Canonical zones are returned unchanged.
+pub fn canonical(tz: Tz) -> Tz {
+    links::LINKS
+        .binary_search_by(|(alias, _)| (*alias).cmp(tz.name()))
+        .ok()
+        .and_then(|i| links::LINKS[i].1.parse().ok())
+        .unwrap_or(tz)
+}
+
+/// Whether a zone describes a place, as opposed to a fixed offset (`Etc/GMT+5`,
+/// `UTC`) or a placeholder (`Factory`). Legacy aliases like `US/Eastern` count
+/// as geographic through their canonical zone.
+pub fn is_geographic(tz: Tz) -> bool {
+    let name = canonical(tz).name();
+    name.contains('/') && !name.starts_with("Etc/")
+}
+
+/// Country a zone is principally used in, for rendering a flag.
+#[derive(Serialize)]
+pub struct RegionHint {
+    pub country: &'static str,
+    pub flag: String,
+}
+
+/// Looks the zone up in zone.tab, falling back to its canonical zone so
+/// legacy names like `US/Eastern` still resolve. UTC and other `Etc/` zones
+/// have no country.
+pub fn region_hint(tz: Tz) -> Option<RegionHint> {
+    let country = country_of(tz.name()).or_else(|| country_of(canonical(tz).name()))?;
+    Some(RegionHint {
+        country,
+        flag: flag_emoji(country),
+    })
+}
+
+fn country_of(zone: &str) -> Option<&'static str> {
+    countries::ZONE_COUNTRIES
+        .binary_search_by(|(name, _)| (*name).cmp(zone))
+        .ok()
+        .map(|i| countries::ZONE_COUNTRIES[i].1)
+}
+
+/// Maps a two-letter country code onto its regional indicator symbols.
+fn flag_emoji(country: &str) -> String {
+    country
+        .chars()
+        .filter_map(|c| char::from_u32(0x1F1E6 + (c as u32).checked_sub('A' as u32)?))
+        .collect()
+}
+
+/// A city a zone can be picked by.
+#[derive(Serialize)]
+pub struct CityZone {
+    pub city: String,
+    pub timezone: &'static str,
+}
+
+/// Outcome of looking a zone up by country and city.
+pub enum LocationMatch {
+    Found(Tz),
+    /// The city is known in more than one zone.
+    Ambiguous(Vec<CityZone>),
+    /// The city isn't known; these are the country's cities that are.
+    NotFound(Vec<CityZone>),
+    UnknownCountry,
+}
+
+/// Finds the zone for a city in a two-letter country. Cities are each zone's
+/// exemplar city from zone.tab plus the extras in `cities`, compared ignoring
+/// case, spaces and punctuation. In a country with a single zone any city
+/// resolves to it.
+pub fn find_by_location(country: &str, city: &str) -> LocationMatch {
+    let country = country.trim().to_ascii_uppercase();
+    let known: Vec<CityZone> = countries::ZONE_COUNTRIES
+        .iter()
+        .filter(|(_, code)| *code == country)
+        .map(|(zone, _)| CityZone {
+            city: zone.rsplit('/').next().unwrap_or(zone).replace('_', " "),
+            timezone: zone,
+        })
+        .chain(
+            cities::CITIES
+                .iter()
+                .filter(|(code, _, _)| *code == country)
+                .map(|(_, city, zone)| CityZone {
+                    city: city.to_string(),
+                    timezone: zone,
+                }),
+        )
+        .collect();
+    if known.is_empty() {
+        return LocationMatch::UnknownCountry;
+    }
+
+    let mut zones: Vec<&str> = known.iter().map(|entry| entry.timezone).collect();
+    zones.sort_unstable();
+    zones.dedup();
+
+    let needle = city_key(city);
+    let (mut matches, mut others): (Vec<CityZone>, Vec<CityZone>) = known
+        .into_iter()
+        .partition(|entry| city_key(&entry.city) == needle);
+    matches.sort_by(|a, b| a.timezone.cmp(b.timezone));
+    matches.dedup_by(|a, b| a.timezone == b.timezone);
+
+    let zone = match (matches.as_slice(), zones.as_slice()) {
+        ([only], _) => Some(only.timezone),
+        ([], [only]) => Some(*only),
+        _ => None,
+    };
+    if let Some(tz) = zone.and_then(|zone| zone.parse().ok()) {
+        return LocationMatch::Found(tz);
+    }
+    if matches.is_empty() {
+        others.sort_by(|a, b| a.city.cmp(&b.city));
+        LocationMatch::NotFound(others)
+    } else {
+        LocationMatch::Ambiguous(matches)
+    }
+}
+
+/// Lowercased letters and digits only, with accents dropped, so "St. John's"
+/// matches `St_Johns` and "São Paulo" matches `Sao_Paulo`.
+fn city_key(city: &str) -> String {
+    city.nfd()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// A display name such as "New York (Eastern Time)" in `locale`, from the
+/// bundled CLDR names. Only the language subtag is used, so `de-AT` gets the
+/// German names. `None` when the language or the zone isn't covered.
+pub fn localized_name(tz: Tz, locale: &str) -> Option<String> {
+    let language = locale
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let column = names::LOCALES.iter().position(|l| *l == language)?;
+
+    let canonical = canonical(tz).name();
+    let metazone = names::ZONE_METAZONES
+        .binary_search_by(|(zone, _)| (*zone).cmp(canonical))
+        .ok()
+        .map(|i| names::ZONE_METAZONES[i].1)?;
+    let metazone_name = names::METAZONE_NAMES
+        .binary_search_by(|(name, _)| (*name).cmp(metazone))
+        .ok()
+        .map(|i| names::METAZONE_NAMES[i].1[column])?;
+
+    // Links listed in zone.tab (Europe/Stockholm) keep their own city; other
+    // aliases (US/Eastern) take the canonical zone's.
+    let zone = if country_of(tz.name()).is_some() {
+        tz.name()
+    } else {
+        canonical
+    };
+    let city = names::CITY_NAMES
+        .binary_search_by(|(name, l, _)| (*name, *l).cmp(&(zone, language.as_str())))
+        .ok()
+        .map(|i| names::CITY_NAMES[i].2.to_string())
+        .unwrap_or_else(|| zone.rsplit('/').next().unwrap_or(zone).replace('_', " "));
+    Some(format!("{} ({})", city, metazone_name))
+}
+
+/// The rules in effect for a zone at one instant.
+#[derive(Serialize, Clone, PartialEq)]
+pub struct OffsetInfo {
+    pub utc_offset: String,
+    pub utc_offset_seconds: i32,
+    pub abbreviation: Option<String>,
+    pub dst: bool,
+}
+
+pub fn offset_at(tz: Tz, at: DateTime<Utc>) -> OffsetInfo {
+    let offset = tz.offset_from_utc_datetime(&at.naive_utc());
+    let seconds = offset.fix().local_minus_utc();
+    OffsetInfo {
+        utc_offset: format_offset(seconds),
+        utc_offset_seconds: seconds,
+        abbreviation: offset.abbreviation().map(String::from),
+        dst: !offset.dst_offset().is_zero(),
+    }
+}
+
+/// Whether the zone's offset differs between mid-January and mid-July of
+/// `year`, i.e. whether it has a summer time at all.
+pub fn observes_dst(tz: Tz, year: i32) -> bool {
+    let sample = |month| {
+        NaiveDate::from_ymd_opt(year, month, 15)
+            .and_then(|date| date.and_hms_opt(12, 0, 0))
+            .map(|at| tz.offset_from_utc_datetime(&at).fix())
+    };
+    sample(1) != sample(7)
+}
+
+/// Formats an offset in seconds as `+HH:MM`.
+pub fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.unsigned_abs() / 60;
+    format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// Describes an offset for people: "5 hours behind UTC", "1 hour ahead of
+/// UTC", "5 hours 30 minutes ahead of UTC", or "at UTC" for zero.
+pub fn offset_phrase(seconds: i32) -> String {
+    let minutes = seconds.unsigned_abs() / 60;
+    let (hours, minutes) = (minutes / 60, minutes % 60);
+    let unit =
+        |count: u32, name: &str| format!("{} {}{}", count, name, if count == 1 { "" } else { "s" });
+    let amount = match (hours, minutes) {
+        (0, 0) => return "at UTC".into(),
+        (hours, 0) => unit(hours, "hour"),
+        (0, minutes) => unit(minutes, "minute"),
+        (hours, minutes) => format!("{} {}", unit(hours, "hour"), unit(minutes, "minute")),
+    };
+    if seconds < 0 {
+        format!("{} behind UTC", amount)
+    } else {
+        format!("{} ahead of UTC", amount)
+    }
+}
+
+#[derive(Serialize)]
+pub struct Transition {
+    pub at: String,
+    #[serde(flatten)]
+    pub offset: OffsetInfo,
+}
+
+/// Finds the next change in offset or abbreviation after `from`.
+///
+/// chrono-tz doesn't expose its transition table, so this samples once a day
+/// and then bisects down to the second. Real zones never change twice within a
+/// day, so nothing is skipped.
+pub fn next_transition(tz: Tz, from: DateTime<Utc>) -> Option<Transition> {
+    let at = transition_after(tz, from)?;
+    Some(Transition {
+        at: at.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        offset: offset_at(tz, at),
+    })
+}
+
+/// The instant of the next change after `from`, searched as described on
+/// `next_transition`.
+pub fn transition_after(tz: Tz, from: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let current = offset_at(tz, from);
+    let at = |secs: i64| DateTime::from_timestamp(secs, 0).unwrap_or(from);
+
+    let mut low = from.timestamp();
+    let mut high = None;
+    for _ in 0..TRANSITION_HORIZON_DAYS {
+        let probe = low + DAY;
+        if offset_at(tz, at(probe)) != current {
+            high = Some(probe);
+            break;
+        }
+        low = probe;
+    }
+    let mut high = high?;
+
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if offset_at(tz, at(mid)) == current {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    Some(at(high))
+}
+
+/// A POSIX `TZ` string (`EST5EDT,M3.2.0,M11.1.0`) for the zone's rules as of
+/// `now`, built from the next two transitions. POSIX can only express one
+/// fixed yearly rule, so zones whose rules are about to change, or that never
+/// return to the current offset within a year, get their current offset as a
+/// fixed zone.
+pub fn posix_tz(tz: Tz, now: DateTime<Utc>) -> String {
+    let current = offset_at(tz, now);
+    let rule = transition_after(tz, now).and_then(|first| {
+        let second = transition_after(tz, first + chrono::Duration::seconds(1))?;
+        if second - now > chrono::Duration::days(366) || offset_at(tz, second) != current {
+            return None;
+        }
+        let other = offset_at(tz, first);
+        let (std, dst, start, end) = match (current.dst, other.dst) {
+            (false, true) => (&current, &other, first, second),
+            (true, false) => (&other, &current, second, first),
+            _ => return None,
+        };
+        let mut out = format!(
+            "{}{}",
+            posix_name(std),
+            posix_offset(std.utc_offset_seconds)
+        );
+        out.push_str(&posix_name(dst));
+        if dst.utc_offset_seconds != std.utc_offset_seconds + 3600 {
+            out.push_str(&posix_offset(dst.utc_offset_seconds));
+        }
+        out.push_str(&posix_rule(tz, start, std.utc_offset_seconds));
+        out.push_str(&posix_rule(tz, end, dst.utc_offset_seconds));
+        Some(out)
+    });
+    rule.unwrap_or_else(|| {
+        format!(
+            "{}{}",
+            posix_name(&current),
+            posix_offset(current.utc_offset_seconds)
+        )
+    })
+}
+
+/// Alphabetic abbreviations go in as-is. Zones without one are named after
+/// their offset the way tzdata does (`<-03>`, `<+0545>`), which must be quoted.
+fn posix_name(offset: &OffsetInfo) -> String {
+    match &offset.abbreviation {
+        Some(name) if name.len() >= 3 && name.chars().all(|c| c.is_ascii_alphabetic()) => {
+            name.clone()
+        }
+        Some(name) => format!("<{}>", name),
+        None => {
+            let numeric = offset.utc_offset.replace(':', "");
+            format!("<{}>", numeric.strip_suffix("00").unwrap_or(&numeric))
+        }
+    }
+}
+
+/// POSIX offsets count hours *west* of UTC, the opposite of ISO 8601.
+fn posix_offset(seconds: i32) -> String {
+    let sign = if seconds > 0 { "-" } else { "" };
+    format!("{}{}", sign, posix_time(seconds.unsigned_abs()))
+}
+
+fn posix_time(seconds: u32) -> String {
+    let (hours, minutes, secs) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match (minutes, secs) {
+        (0, 0) => hours.to_string(),
+        (_, 0) => format!("{}:{:02}", hours, minutes),
+        _ => format!("{}:{:02}:{:02}", hours, minutes, secs),
+    }
+}
+
+/// `,Mm.w.d[/time]` for a change at `at`, given in the local time before it.
+/// A date in the month's last seven days becomes week 5, "last". Days 22-28
+/// can also be a fourth week (Paraguay's `Sun>=22`), so those are checked
+/// against the same change in the following years until one tells them apart.
+fn posix_rule(tz: Tz, at: DateTime<Utc>, offset_before: i32) -> String {
+    let local = local_before(at, offset_before);
+    let mut week = week_of_month(local.date());
+    let mut later = at;
+    while week == 5 && local.day() <= 28 {
+        let Some(next) = transition_after(tz, later + chrono::Duration::days(300)) else {
+            break;
+        };
+        let next_local = local_before(next, offset_before);
+        if next_local.month() != local.month() || next_local.year() > local.year() + 7 {
+            break;
+        }
+        if week_of_month(next_local.date()) == 4 {
+            week = 4;
+        } else if next_local.day() > 28 {
+            break;
+        }
+        later = next;
+    }
+    let weekday = local.weekday().num_days_from_sunday();
+    let mut rule = format!(",M{}.{}.{}", local.month(), week, weekday);
+    let time = local.num_seconds_from_midnight();
+    if time != 2 * 3600 {
+        rule.push('/');
+        rule.push_str(&posix_time(time));
+    }
+    rule
+}
+
+fn local_before(at: DateTime<Utc>, offset_before: i32) -> NaiveDateTime {
+    (at + chrono::Duration::seconds(offset_before.into())).naive_utc()
+}
+
+fn week_of_month(date: NaiveDate) -> u32 {
+    let (year, month, day) = (date.year(), date.month(), date.day());
+    let days_in_month = NaiveDate::from_ymd_opt(year + i32::from(month == 12), month % 12 + 1, 1)
+        .and_then(|next| next.pred_opt())
+        .map_or(31, |last| last.day());
+    if day + 7 > days_in_month {
+        5
+    } else {
+        (day - 1) / 7 + 1
+    }
+}
+
+/// Largest shift `parse_shift` accepts, in either direction.
+const MAX_SHIFT_SECONDS: i64 = 366 * DAY;
+
+/// Parses a signed duration such as `3h`, `-30m` or `1d2h30m` into seconds.
+/// Units are `d`, `h`, `m` and `s`.
+pub fn parse_shift(input: &str) -> Result<i64, String> {
+    let invalid = || format!("{:?} is not a duration like 3h, -30m or 1h30m", input);
+    let trimmed = input.trim();
+    let (sign, body) = match trimmed.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    if body.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total: i64 = 0;
+    let mut digits = String::new();
+    for c in body.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => DAY,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        let amount: i64 = digits.parse().map_err(|_| invalid())?;
+        digits.clear();
+        total = amount
+            .checked_mul(unit)
+            .and_then(|secs| total.checked_add(secs))
+            .filter(|secs| *secs <= MAX_SHIFT_SECONDS)
+            .ok_or_else(|| "Duration may be at most 366 days".to_string())?;
+    }
+    if !digits.is_empty() {
+        return Err(invalid());
+    }
+    Ok(sign * total)
+}
+
+/// Named renderings for a local time, selectable per request (`fmt=`) or
+/// stored as a user's preference.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum TimeFormat {
+    Iso,
+    #[default]
+    Full,
+    Date,
+    Time12,
+    Time24,
+}
+
+impl TimeFormat {
+    pub const ALL: &'static [TimeFormat] = &[
+        TimeFormat::Iso,
+        TimeFormat::Full,
+        TimeFormat::Date,
+        TimeFormat::Time12,
+        TimeFormat::Time24,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TimeFormat::Iso => "iso",
+            TimeFormat::Full => "full",
+            TimeFormat::Date => "date",
+            TimeFormat::Time12 => "time12",
+            TimeFormat::Time24 => "time24",
+        }
+    }
+
+    pub fn render(self, at: &DateTime<Tz>) -> String {
+        match self {
+            TimeFormat::Iso => at.to_rfc3339_opts(SecondsFormat::Secs, false),
+            TimeFormat::Full => at.format("%A, %B %-d, %Y %-I:%M %p").to_string(),
+            TimeFormat::Date => at.format("%Y-%m-%d").to_string(),
+            TimeFormat::Time12 => at.format("%-I:%M %p").to_string(),
+            TimeFormat::Time24 => at.format("%H:%M").to_string(),
+        }
+    }
+}
+
+impl FromStr for TimeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TimeFormat::ALL
+            .iter()
+            .copied()
+            .find(|format| format.as_str() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = TimeFormat::ALL.iter().map(|f| f.as_str()).collect();
+                format!(
+                    "Unknown time format {:?}; expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// One instant in every `TimeFormat`, plus Unix seconds and Discord markup, for
+/// clients that would otherwise ask once per format.
+#[derive(Serialize)]
+pub struct Formats {
+    #[serde(flatten)]
+    presets: BTreeMap<&'static str, String>,
+    unix: i64,
+    discord: String,
+}
+
+pub fn formats(at: &DateTime<Tz>) -> Formats {
+    let unix = at.timestamp();
+    Formats {
+        presets: TimeFormat::ALL
+            .iter()
+            .map(|format| (format.as_str(), format.render(at)))
+            .collect(),
+        unix,
+        discord: format!("<t:{}:f>", unix),
+    }
+}
+
+/// The zone whose name matches `input` ignoring ASCII case, if exactly one
+/// does.
+pub fn find_case_insensitive(input: &str) -> Option<Tz> {
+    let mut matches = chrono_tz::TZ_VARIANTS
+        .iter()
+        .filter(|tz| tz.name().eq_ignore_ascii_case(input));
+    match (matches.next(), matches.next()) {
+        (Some(tz), None) => Some(*tz),
+        _ => None,
+    }
+}
+
+/// IANA names are short; anything longer is rejected before parsing.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Known zone names close to `input`, best match first: case-insensitive
+/// matches, then typos in the full name or the city, then names containing
+/// the input. Inputs longer than any zone name get none, since each
+/// candidate costs an edit distance quadratic in the input.
+pub fn suggest(input: &str, limit: usize) -> Vec<&'static str> {
+    let input = input.trim();
+    if input.is_empty() || input.len() > MAX_NAME_LEN {
+        return Vec::new();
+    }
+    let needle = input.to_ascii_lowercase().replace(' ', "_");
+    let needle_city = needle.rsplit('/').next().unwrap_or(&needle);
+    // Short inputs only tolerate short typos, or "tokyo" would suggest "ROK".
+    let max_full = (needle.len() / 3).min(3);
+    let max_city = (needle_city.len() / 3).min(2);
+
+    let mut scored: Vec<(usize, &'static str)> = chrono_tz::TZ_VARIANTS
+        .iter()
+        .filter_map(|tz| {
+            let name = tz.name();
+            let lower = name.to_ascii_lowercase();
+            let city = lower.rsplit('/').next().unwrap_or(&lower);
+            let score = if lower == needle {
+                0
+            } else if city == needle_city {
+                1
+            } else {
+                let full = edit_distance(&lower, &needle);
+                let city_distance = edit_distance(city, needle_city);
+                if full <= max_full {
+                    1 + full
+                } else if city_distance <= max_city {
+                    3 + city_distance
+                } else if lower.contains(&needle) {
+                    6
+                } else {
+                    return None;
+                }
+            };
+            Some((score, name))
+        })
+        .collect();
+
+    scored.sort();
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(country: &str, city: &str) -> Option<&'static str> {
+        match find_by_location(country, city) {
+            LocationMatch::Found(tz) => Some(tz.name()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn exact_city() {
+        assert_eq!(zone("GB", "London"), Some("Europe/London"));
+        assert_eq!(zone("US", "Chicago"), Some("America/Chicago"));
+    }
+
+    #[test]
+    fn ignores_case_spaces_and_punctuation() {
+        assert_eq!(zone("us", "new york"), Some("America/New_York"));
+        assert_eq!(zone(" ca ", "St. John's"), Some("America/St_Johns"));
+    }
+
+    #[test]
+    fn ignores_accents() {
+        assert_eq!(zone("BR", "São Paulo"), Some("America/Sao_Paulo"));
+    }
+
+    #[test]
+    fn single_zone_country_takes_any_city() {
+        assert_eq!(zone("FR", "Lyon"), Some("Europe/Paris"));
+    }
+
+    #[test]
+    fn city_in_several_zones_is_ambiguous() {
+        let LocationMatch::Ambiguous(matches) = find_by_location("US", "Portland") else {
+            panic!("Portland should be ambiguous");
+        };
+        let zones: Vec<&str> = matches.iter().map(|entry| entry.timezone).collect();
+        assert_eq!(zones, ["America/Los_Angeles", "America/New_York"]);
+    }
+
+    #[test]
+    fn unknown_city_lists_the_country() {
+        let LocationMatch::NotFound(others) = find_by_location("US", "Atlantis") else {
+            panic!("Atlantis should not be found");
+        };
+        assert!(others.iter().any(|entry| entry.city == "Chicago"));
+    }
+
+    #[test]
+    fn unknown_country() {
+        assert!(matches!(
+            find_by_location("ZZ", "London"),
+            LocationMatch::UnknownCountry
+        ));
+    }
+
+    fn posix(name: &str, now: &str) -> String {
+        posix_tz(name.parse().unwrap(), now.parse().unwrap())
+    }
+
+    #[test]
+    fn posix_northern_dst() {
+        assert_eq!(
+            posix("America/New_York", "2025-06-01T12:00:00Z"),
+            "EST5EDT,M3.2.0,M11.1.0"
+        );
+        assert_eq!(
+            posix("America/New_York", "2025-01-15T12:00:00Z"),
+            "EST5EDT,M3.2.0,M11.1.0"
+        );
+    }
+
+    #[test]
+    fn posix_southern_dst() {
+        assert_eq!(
+            posix("Australia/Sydney", "2025-06-01T12:00:00Z"),
+            "AEST-10AEDT,M10.1.0,M4.1.0/3"
+        );
+    }
+
+    #[test]
+    fn posix_without_dst() {
+        assert_eq!(posix("Asia/Tokyo", "2025-06-01T12:00:00Z"), "JST-9");
+    }
+
+    #[test]
+    fn posix_fractional_offsets() {
+        assert_eq!(
+            posix("Asia/Kathmandu", "2025-06-01T12:00:00Z"),
+            "<+0545>-5:45"
+        );
+        assert_eq!(
+            posix("Australia/Adelaide", "2025-06-01T12:00:00Z"),
+            "ACST-9:30ACDT,M10.1.0,M4.1.0/3"
+        );
+    }
+
+    /// Dublin's summer time is its standard time, so winter is a negative DST.
+    #[test]
+    fn posix_negative_dst() {
+        assert_eq!(
+            posix("Europe/Dublin", "2025-06-01T12:00:00Z"),
+            "IST-1GMT0,M10.5.0,M3.5.0/1"
+        );
+    }
+
+    /// Paraguay's `Mar Sun>=22` fell on 27 March 2022, which is also the last
+    /// Sunday. 2024's 24 March shows it to be the fourth.
+    #[test]
+    fn posix_fourth_week_in_last_seven_days() {
+        assert_eq!(
+            posix("America/Asuncion", "2021-06-01T12:00:00Z"),
+            "<-04>4<-03>,M10.1.0/0,M3.4.0/0"
+        );
+        assert_eq!(
+            posix("Europe/London", "2025-06-01T12:00:00Z"),
+            "GMT0BST,M3.5.0/1,M10.5.0"
+        );
+    }
+}
@@ -0,0 +1,469 @@
+// A subset of CLDR's metazone and exemplar city names for the most used
+// zones, in the locales listed in `LOCALES`. Zones without an entry fall
+// back to their IANA name. Extend from CLDR's `timeZoneNames` data.
+
+/// Languages the tables below have names for, in column order.
+pub static LOCALES: [&str; 4] = ["de", "en", "es", "fr"];
+
+/// `(canonical zone, metazone)` pairs sorted by zone.
+pub static ZONE_METAZONES: &[(&str, &str)] = &[
+    ("Africa/Abidjan", "GMT"),
+    ("Africa/Cairo", "Europe_Eastern"),
+    ("Africa/Johannesburg", "Africa_Southern"),
+    ("Africa/Lagos", "Africa_Western"),
+    ("Africa/Maputo", "Africa_Central"),
+    ("Africa/Nairobi", "Africa_Eastern"),
+    ("America/Adak", "Hawaii_Aleutian"),
+    ("America/Anchorage", "Alaska"),
+    ("America/Argentina/Buenos_Aires", "Argentina"),
+    ("America/Boise", "America_Mountain"),
+    ("America/Cancun", "America_Eastern"),
+    ("America/Chicago", "America_Central"),
+    ("America/Costa_Rica", "America_Central"),
+    ("America/Denver", "America_Mountain"),
+    ("America/Detroit", "America_Eastern"),
+    ("America/Edmonton", "America_Mountain"),
+    ("America/Guatemala", "America_Central"),
+    ("America/Halifax", "Atlantic"),
+    ("America/Indiana/Indianapolis", "America_Eastern"),
+    ("America/Jamaica", "America_Eastern"),
+    ("America/Juneau", "Alaska"),
+    ("America/Kentucky/Louisville", "America_Eastern"),
+    ("America/Los_Angeles", "America_Pacific"),
+    ("America/Mexico_City", "America_Central"),
+    ("America/Monterrey", "America_Central"),
+    ("America/New_York", "America_Eastern"),
+    ("America/Panama", "America_Eastern"),
+    ("America/Phoenix", "America_Mountain"),
+    ("America/Puerto_Rico", "Atlantic"),
+    ("America/Regina", "America_Central"),
+    ("America/Sao_Paulo", "Brasilia"),
+    ("America/St_Johns", "Newfoundland"),
+    ("America/Tijuana", "America_Pacific"),
+    ("America/Toronto", "America_Eastern"),
+    ("America/Vancouver", "America_Pacific"),
+    ("America/Winnipeg", "America_Central"),
+    ("Asia/Bangkok", "Indochina"),
+    ("Asia/Beirut", "Europe_Eastern"),
+    ("Asia/Dubai", "Gulf"),
+    ("Asia/Ho_Chi_Minh", "Indochina"),
+    ("Asia/Hong_Kong", "Hong_Kong"),
+    ("Asia/Jerusalem", "Israel"),
+    ("Asia/Karachi", "Pakistan"),
+    ("Asia/Kolkata", "India"),
+    ("Asia/Seoul", "Korea"),
+    ("Asia/Shanghai", "China"),
+    ("Asia/Singapore", "Singapore"),
+    ("Asia/Tokyo", "Japan"),
+    ("Atlantic/Bermuda", "Atlantic"),
+    ("Atlantic/Canary", "Europe_Western"),
+    ("Atlantic/Madeira", "Europe_Western"),
+    ("Australia/Adelaide", "Australia_Central"),
+    ("Australia/Brisbane", "Australia_Eastern"),
+    ("Australia/Darwin", "Australia_Central"),
+    ("Australia/Hobart", "Australia_Eastern"),
+    ("Australia/Melbourne", "Australia_Eastern"),
+    ("Australia/Perth", "Australia_Western"),
+    ("Australia/Sydney", "Australia_Eastern"),
+    ("Europe/Athens", "Europe_Eastern"),
+    ("Europe/Belgrade", "Europe_Central"),
+    ("Europe/Berlin", "Europe_Central"),
+    ("Europe/Brussels", "Europe_Central"),
+    ("Europe/Bucharest", "Europe_Eastern"),
+    ("Europe/Budapest", "Europe_Central"),
+    ("Europe/Dublin", "GMT"),
+    ("Europe/Helsinki", "Europe_Eastern"),
+    ("Europe/Kyiv", "Europe_Eastern"),
+    ("Europe/Lisbon", "Europe_Western"),
+    ("Europe/London", "GMT"),
+    ("Europe/Madrid", "Europe_Central"),
+    ("Europe/Moscow", "Moscow"),
+    ("Europe/Paris", "Europe_Central"),
+    ("Europe/Prague", "Europe_Central"),
+    ("Europe/Rome", "Europe_Central"),
+    ("Europe/Sofia", "Europe_Eastern"),
+    ("Europe/Vienna", "Europe_Central"),
+    ("Europe/Warsaw", "Europe_Central"),
+    ("Europe/Zurich", "Europe_Central"),
+    ("Pacific/Auckland", "New_Zealand"),
+    ("Pacific/Honolulu", "Hawaii_Aleutian"),
+];
+
+/// Generic metazone names, one per `LOCALES` entry, sorted by metazone.
+pub static METAZONE_NAMES: &[(&str, [&str; 4])] = &[
+    (
+        "Africa_Central",
+        [
+            "Zentralafrikanische Zeit",
+            "Central Africa Time",
+            "hora de África central",
+            "heure normale d’Afrique centrale",
+        ],
+    ),
+    (
+        "Africa_Eastern",
+        [
+            "Ostafrikanische Zeit",
+            "East Africa Time",
+            "hora de África oriental",
+            "heure normale d’Afrique de l’Est",
+        ],
+    ),
+    (
+        "Africa_Southern",
+        [
+            "Südafrikanische Zeit",
+            "South Africa Standard Time",
+            "hora de Sudáfrica",
+            "heure normale d’Afrique méridionale",
+        ],
+    ),
+    (
+        "Africa_Western",
+        [
+            "Westafrikanische Zeit",
+            "West Africa Time",
+            "hora de África occidental",
+            "heure d’Afrique de l’Ouest",
+        ],
+    ),
+    (
+        "Alaska",
+        [
+            "Alaska-Zeit",
+            "Alaska Time",
+            "hora de Alaska",
+            "heure de l’Alaska",
+        ],
+    ),
+    (
+        "America_Central",
+        [
+            "Nordamerikanische Zentralzeit",
+            "Central Time",
+            "hora central",
+            "heure du centre nord-américain",
+        ],
+    ),
+    (
+        "America_Eastern",
+        [
+            "Nordamerikanische Ostküstenzeit",
+            "Eastern Time",
+            "hora oriental",
+            "heure de l’Est nord-américain",
+        ],
+    ),
+    (
+        "America_Mountain",
+        [
+            "Rocky-Mountain-Zeit",
+            "Mountain Time",
+            "hora de las Montañas Rocosas",
+            "heure des Rocheuses",
+        ],
+    ),
+    (
+        "America_Pacific",
+        [
+            "Nordamerikanische Westküstenzeit",
+            "Pacific Time",
+            "hora del Pacífico",
+            "heure du Pacifique nord-américain",
+        ],
+    ),
+    (
+        "Argentina",
+        [
+            "Argentinische Zeit",
+            "Argentina Time",
+            "hora de Argentina",
+            "heure de l’Argentine",
+        ],
+    ),
+    (
+        "Atlantic",
+        [
+            "Atlantik-Zeit",
+            "Atlantic Time",
+            "hora del Atlántico",
+            "heure de l’Atlantique",
+        ],
+    ),
+    (
+        "Australia_Central",
+        [
+            "Zentralaustralische Zeit",
+            "Central Australia Time",
+            "hora de Australia central",
+            "heure du centre de l’Australie",
+        ],
+    ),
+    (
+        "Australia_Eastern",
+        [
+            "Ostaustralische Zeit",
+            "Eastern Australia Time",
+            "hora de Australia oriental",
+            "heure de l’Est de l’Australie",
+        ],
+    ),
+    (
+        "Australia_Western",
+        [
+            "Westaustralische Zeit",
+            "Western Australia Time",
+            "hora de Australia occidental",
+            "heure de l’Ouest de l’Australie",
+        ],
+    ),
+    (
+        "Brasilia",
+        [
+            "Brasília-Zeit",
+            "Brasilia Time",
+            "hora de Brasilia",
+            "heure de Brasilia",
+        ],
+    ),
+    (
+        "China",
+        [
+            "Chinesische Zeit",
+            "China Time",
+            "hora de China",
+            "heure de la Chine",
+        ],
+    ),
+    (
+        "Europe_Central",
+        [
+            "Mitteleuropäische Zeit",
+            "Central European Time",
+            "hora de Europa central",
+            "heure d’Europe centrale",
+        ],
+    ),
+    (
+        "Europe_Eastern",
+        [
+            "Osteuropäische Zeit",
+            "Eastern European Time",
+            "hora de Europa oriental",
+            "heure d’Europe de l’Est",
+        ],
+    ),
+    (
+        "Europe_Western",
+        [
+            "Westeuropäische Zeit",
+            "Western European Time",
+            "hora de Europa occidental",
+            "heure d’Europe de l’Ouest",
+        ],
+    ),
+    (
+        "GMT",
+        [
+            "Mittlere Greenwich-Zeit",
+            "Greenwich Mean Time",
+            "hora del meridiano de Greenwich",
+            "heure moyenne de Greenwich",
+        ],
+    ),
+    (
+        "Gulf",
+        [
+            "Golf-Zeit",
+            "Gulf Standard Time",
+            "hora del Golfo",
+            "heure du Golfe",
+        ],
+    ),
+    (
+        "Hawaii_Aleutian",
+        [
+            "Hawaii-Aleuten-Zeit",
+            "Hawaii-Aleutian Time",
+            "hora de Hawái-Aleutianas",
+            "heure d’Hawaï - Aléoutiennes",
+        ],
+    ),
+    (
+        "Hong_Kong",
+        [
+            "Hongkong-Zeit",
+            "Hong Kong Time",
+            "hora de Hong Kong",
+            "heure de Hong Kong",
+        ],
+    ),
+    (
+        "India",
+        [
+            "Indische Normalzeit",
+            "India Standard Time",
+            "hora estándar de la India",
+            "heure de l’Inde",
+        ],
+    ),
+    (
+        "Indochina",
+        [
+            "Indochina-Zeit",
+            "Indochina Time",
+            "hora de Indochina",
+            "heure d’Indochine",
+        ],
+    ),
+    (
+        "Israel",
+        [
+            "Israelische Zeit",
+            "Israel Time",
+            "hora de Israel",
+            "heure d’Israël",
+        ],
+    ),
+    (
+        "Japan",
+        [
+            "Japanische Zeit",
+            "Japan Time",
+            "hora de Japón",
+            "heure du Japon",
+        ],
+    ),
+    (
+        "Korea",
+        [
+            "Koreanische Zeit",
+            "Korean Time",
+            "hora de Corea",
+            "heure de la Corée",
+        ],
+    ),
+    (
+        "Moscow",
+        [
+            "Moskauer Zeit",
+            "Moscow Time",
+            "hora de Moscú",
+            "heure de Moscou",
+        ],
+    ),
+    (
+        "New_Zealand",
+        [
+            "Neuseeland-Zeit",
+            "New Zealand Time",
+            "hora de Nueva Zelanda",
+            "heure de la Nouvelle-Zélande",
+        ],
+    ),
+    (
+        "Newfoundland",
+        [
+            "Neufundland-Zeit",
+            "Newfoundland Time",
+            "hora de Terranova",
+            "heure de Terre-Neuve",
+        ],
+    ),
+    (
+        "Pakistan",
+        [
+            "Pakistanische Zeit",
+            "Pakistan Time",
+            "hora de Pakistán",
+            "heure du Pakistan",
+        ],
+    ),
+    (
+        "Singapore",
+        [
+            "Singapur-Zeit",
+            "Singapore Standard Time",
+            "hora de Singapur",
+            "heure de Singapour",
+        ],
+    ),
+];
+
+/// `(zone, locale, city)` where a locale's name for the city differs from
+/// the zone's own, sorted by zone and locale.
+pub static CITY_NAMES: &[(&str, &str, &str)] = &[
+    ("Africa/Cairo", "de", "Kairo"),
+    ("Africa/Cairo", "es", "El Cairo"),
+    ("Africa/Cairo", "fr", "Le Caire"),
+    ("America/Mexico_City", "de", "Mexiko-Stadt"),
+    ("America/Mexico_City", "es", "Ciudad de México"),
+    ("America/Mexico_City", "fr", "Mexico"),
+    ("America/New_York", "es", "Nueva York"),
+    ("America/Sao_Paulo", "de", "São Paulo"),
+    ("America/Sao_Paulo", "es", "São Paulo"),
+    ("America/Sao_Paulo", "fr", "São Paulo"),
+    ("America/St_Johns", "de", "St. John’s"),
+    ("America/St_Johns", "en", "St. John’s"),
+    ("America/St_Johns", "es", "San Juan de Terranova"),
+    ("America/St_Johns", "fr", "Saint-Jean de Terre-Neuve"),
+    ("Asia/Dubai", "es", "Dubái"),
+    ("Asia/Dubai", "fr", "Dubaï"),
+    ("Asia/Ho_Chi_Minh", "de", "Ho-Chi-Minh-Stadt"),
+    ("Asia/Ho_Chi_Minh", "es", "Ciudad Ho Chi Minh"),
+    ("Asia/Ho_Chi_Minh", "fr", "Hô-Chi-Minh-Ville"),
+    ("Asia/Hong_Kong", "de", "Hongkong"),
+    ("Asia/Jerusalem", "es", "Jerusalén"),
+    ("Asia/Jerusalem", "fr", "Jérusalem"),
+    ("Asia/Kolkata", "es", "Calcuta"),
+    ("Asia/Kolkata", "fr", "Calcutta"),
+    ("Asia/Seoul", "es", "Seúl"),
+    ("Asia/Seoul", "fr", "Séoul"),
+    ("Asia/Singapore", "de", "Singapur"),
+    ("Asia/Singapore", "es", "Singapur"),
+    ("Asia/Singapore", "fr", "Singapour"),
+    ("Asia/Tokyo", "de", "Tokio"),
+    ("Asia/Tokyo", "es", "Tokio"),
+    ("Atlantic/Canary", "de", "Kanaren"),
+    ("Atlantic/Canary", "es", "Canarias"),
+    ("Atlantic/Canary", "fr", "Îles Canaries"),
+    ("Europe/Athens", "de", "Athen"),
+    ("Europe/Athens", "es", "Atenas"),
+    ("Europe/Athens", "fr", "Athènes"),
+    ("Europe/Belgrade", "de", "Belgrad"),
+    ("Europe/Belgrade", "es", "Belgrado"),
+    ("Europe/Berlin", "es", "Berlín"),
+    ("Europe/Brussels", "de", "Brüssel"),
+    ("Europe/Brussels", "es", "Bruselas"),
+    ("Europe/Brussels", "fr", "Bruxelles"),
+    ("Europe/Bucharest", "de", "Bukarest"),
+    ("Europe/Bucharest", "es", "Bucarest"),
+    ("Europe/Bucharest", "fr", "Bucarest"),
+    ("Europe/Copenhagen", "de", "Kopenhagen"),
+    ("Europe/Copenhagen", "es", "Copenhague"),
+    ("Europe/Copenhagen", "fr", "Copenhague"),
+    ("Europe/Kyiv", "de", "Kiew"),
+    ("Europe/Kyiv", "es", "Kiev"),
+    ("Europe/Lisbon", "de", "Lissabon"),
+    ("Europe/Lisbon", "es", "Lisboa"),
+    ("Europe/Lisbon", "fr", "Lisbonne"),
+    ("Europe/London", "es", "Londres"),
+    ("Europe/London", "fr", "Londres"),
+    ("Europe/Moscow", "de", "Moskau"),
+    ("Europe/Moscow", "es", "Moscú"),
+    ("Europe/Moscow", "fr", "Moscou"),
+    ("Europe/Paris", "es", "París"),
+    ("Europe/Prague", "de", "Prag"),
+    ("Europe/Prague", "es", "Praga"),
+    ("Europe/Rome", "de", "Rom"),
+    ("Europe/Rome", "es", "Roma"),
+    ("Europe/Stockholm", "es", "Estocolmo"),
+    ("Europe/Vienna", "de", "Wien"),
+    ("Europe/Vienna", "es", "Viena"),
+    ("Europe/Vienna", "fr", "Vienne"),
+    ("Europe/Warsaw", "de", "Warschau"),
+    ("Europe/Warsaw", "es", "Varsovia"),
+    ("Europe/Warsaw", "fr", "Varsovie"),
+    ("Europe/Zurich", "de", "Zürich"),
+    ("Europe/Zurich", "es", "Zúrich"),
+];
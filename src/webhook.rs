@@ -0,0 +1,120 @@
+use crate::clock::unix_now;
+use crate::config::WebhookConfig;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::Duration;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+const MAX_ATTEMPTS: u32 = 4;
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimezoneAction {
+    Set,
+    Delete,
+}
+
+#[derive(Clone, Serialize)]
+pub struct TimezoneEvent {
+    pub user_id: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+    pub action: TimezoneAction,
+    pub at: u64,
+    /// Unique per event and unchanged across retries, so receivers can drop
+    /// duplicates.
+    pub nonce: String,
+}
+
+impl TimezoneEvent {
+    pub fn new(
+        user_id: &str,
+        old: Option<String>,
+        new: Option<String>,
+        action: TimezoneAction,
+    ) -> Self {
+        TimezoneEvent {
+            user_id: user_id.to_string(),
+            old,
+            new,
+            action,
+            at: unix_now(),
+            nonce: Uuid::new_v4().simple().to_string(),
+        }
+    }
+}
+
+/// Delivers `event` to the configured webhook in the background so the write
+/// that triggered it never waits on the receiver.
+pub fn dispatch(http: &reqwest::Client, config: Option<&WebhookConfig>, event: TimezoneEvent) {
+    let Some(config) = config.cloned() else {
+        return;
+    };
+    let http = http.clone();
+
+    tokio::spawn(async move {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(err) => {
+                error!("Failed to serialize webhook event: {}", err);
+                return;
+            }
+        };
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            match deliver(&http, &config, &body).await {
+                Ok(()) => return,
+                Err(err) if attempt < MAX_ATTEMPTS => {
+                    warn!("Webhook attempt {} failed: {}", attempt, err);
+                    tokio::time::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+                }
+                Err(err) => {
+                    error!(
+                        "Dropping webhook event for {} after {} attempts: {}",
+                        event.user_id, MAX_ATTEMPTS, err
+                    );
+                }
+            }
+        }
+    });
+}
+
+async fn deliver(
+    http: &reqwest::Client,
+    config: &WebhookConfig,
+    body: &[u8],
+) -> Result<(), String> {
+    let mut req = http
+        .post(&config.url)
+        .header("Content-Type", "application/json")
+        .body(body.to_vec());
+
+    // The timestamp is refreshed on every attempt and covered by the signature,
+    // so a captured request can't be replayed outside the receiver's window.
+    let timestamp = unix_now();
+    req = req.header("X-Webhook-Timestamp", timestamp);
+    if let Some(secret) = &config.secret {
+        req = req.header(
+            "X-Webhook-Signature",
+            format!("sha256={}", sign(secret, timestamp, body)),
+        );
+    }
+
+    let res = req.send().await.map_err(|err| err.to_string())?;
+    if !res.status().is_success() {
+        return Err(format!("receiver responded with {}", res.status()));
+    }
+    Ok(())
+}
+
+/// HMAC-SHA256 over `<timestamp>.<body>`.
+fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}